@@ -1,43 +1,202 @@
-use std::str::FromStr;
-
 // use crate::calc::NetPositiveCycle;
 // use fuels::types::transaction::TxPolicies;
 use mira_v1::interface::MiraAmmContract;
+use std::{borrow::Cow, path::PathBuf};
+use tempfile::TempDir;
 
-use ethers::types::{I256, U256};
+use ethers::types::U256;
 use fuels::{
     accounts::{wallet::WalletUnlocked, Account},
+    crypto::Hasher,
     macros::abigen,
     prelude::Result,
-    programs::calls::Execution,
+    programs::{
+        calls::{CallHandler, Execution, ScriptCall},
+        responses::{CallResponse, SubmitResponse},
+    },
+    tx::Receipt,
     types::{
-        input::Input, output::Output, transaction::TxPolicies,
-        transaction_builders::VariableOutputPolicy, AssetId, ContractId,
+        errors::{transaction::Reason, Error},
+        input::Input,
+        output::Output,
+        transaction::TxPolicies,
+        transaction_builders::VariableOutputPolicy,
+        AssetId,
     },
 };
 use mira_v1::interface::PoolId;
 
-use crate::{calc::NetPositiveCycle, constants::CONTRACT_ID};
+use crate::{calc::NetPositiveCycle, types::Config};
+
+/// What happened to a cycle handed to `execute_cycle`
+#[derive(Debug)]
+pub struct ExecutionOutcome {
+    pub tx_id: Option<String>,
+    // Amount and asset entering each hop followed by the final output, as the script computed them
+    pub amounts: Vec<(u64, AssetId)>,
+    pub gas_used: u64,
+    // Receipts of the submitted transaction, including a reverted one. Empty if nothing was
+    // sent or the node rejected it.
+    pub receipts: Vec<Receipt>,
+    pub status: ExecutionStatus,
+}
+
+#[derive(Debug)]
+pub enum ExecutionStatus {
+    // Landed on-chain
+    Success,
+    // Simulation passed but this was a dry run, nothing was sent
+    Simulated,
+    // Simulation failed, nothing was sent
+    SimulationFailed(String),
+    // Simulation came out below what the cycle was priced at, nothing was sent
+    BelowExpected { simulated: u64, expected: u64 },
+    // Sent and reverted or rejected by the node
+    Reverted(String),
+}
 
-pub async fn send_multi_hop(wallet: &WalletUnlocked, profitable_cycle: NetPositiveCycle) {
+/// Simulates `profitable_cycle` against current chain state and, unless `config.dry_run` is
+/// set, submits it once the simulated final amount matches what the cycle was priced at
+pub async fn execute_cycle(
+    wallet: &WalletUnlocked,
+    config: &Config,
+    profitable_cycle: &NetPositiveCycle,
+) -> ExecutionOutcome {
     let policies = TxPolicies::default();
-    let amount_in: u64 =
-        scale_and_convert_to_u64_from_u256(profitable_cycle.optimal_in, 1000000).unwrap();
+    let (amount_in, amount_out_min) = swap_limits(profitable_cycle);
     println!("optimal amount_in: {:#?}", amount_in);
-    println!("token_in: {:#?}", profitable_cycle.cycle_ids[0].0);
-    let tx = preview_swap_exact_input(
+    println!("token_in: {:#?}", profitable_cycle.token_in);
+
+    let simulated = match preview_swap_exact_input(
         wallet,
+        config,
         amount_in,
-        profitable_cycle.cycle_ids[0].0,
-        profitable_cycle.profit.as_u64(),
-        profitable_cycle.cycle_ids,
+        profitable_cycle.token_in,
+        amount_out_min,
+        profitable_cycle.cycle_ids.clone(),
         999999999,
         Some(policies),
     )
     .await
-    .unwrap();
-    println!("tx: {:#?}", tx);
+    {
+        Ok(response) => response,
+        Err(e) => {
+            return ExecutionOutcome {
+                tx_id: None,
+                amounts: Vec::new(),
+                gas_used: 0,
+                receipts: Vec::new(),
+                status: ExecutionStatus::SimulationFailed(e.to_string()),
+            }
+        }
+    };
+
+    let simulated_out = simulated.value.last().map_or(0, |(amount, _)| *amount);
+    if simulated_out < amount_out_min || simulated_out <= amount_in {
+        return ExecutionOutcome {
+            tx_id: None,
+            amounts: simulated.value,
+            gas_used: simulated.gas_used,
+            receipts: Vec::new(),
+            status: ExecutionStatus::BelowExpected {
+                simulated: simulated_out,
+                expected: amount_out_min,
+            },
+        };
+    }
+    if config.dry_run {
+        return ExecutionOutcome {
+            tx_id: None,
+            amounts: simulated.value,
+            gas_used: simulated.gas_used,
+            receipts: Vec::new(),
+            status: ExecutionStatus::Simulated,
+        };
+    }
+
+    let submitted = match submit_swap_exact_input(
+        wallet,
+        config,
+        amount_in,
+        profitable_cycle.token_in,
+        amount_out_min,
+        profitable_cycle.cycle_ids.clone(),
+        999999999,
+        Some(policies),
+    )
+    .await
+    {
+        Ok(submitted) => submitted,
+        Err(e) => {
+            return ExecutionOutcome {
+                tx_id: None,
+                amounts: simulated.value,
+                gas_used: 0,
+                receipts: Vec::new(),
+                status: ExecutionStatus::Reverted(e.to_string()),
+            }
+        }
+    };
+
+    let tx_id = Some(format!("{:#x}", submitted.tx_id()));
+    match submitted.response().await {
+        Ok(response) => ExecutionOutcome {
+            tx_id,
+            amounts: response.value,
+            gas_used: response.gas_used,
+            receipts: response.receipts,
+            status: ExecutionStatus::Success,
+        },
+        // Landed but reverted, its receipts say where
+        Err(Error::Transaction(Reason::Reverted {
+            reason, receipts, ..
+        })) => ExecutionOutcome {
+            tx_id,
+            amounts: simulated.value,
+            gas_used: 0,
+            receipts,
+            status: ExecutionStatus::Reverted(reason),
+        },
+        Err(e) => ExecutionOutcome {
+            tx_id,
+            amounts: simulated.value,
+            gas_used: 0,
+            receipts: Vec::new(),
+            status: ExecutionStatus::Reverted(e.to_string()),
+        },
+    }
+}
+
+/// Gas the swap script uses to run `cycle`, measured by simulating it
+pub async fn simulate_gas(
+    wallet: &WalletUnlocked,
+    config: &Config,
+    cycle: &NetPositiveCycle,
+) -> Result<u64> {
+    let (amount_in, amount_out_min) = swap_limits(cycle);
+    let response = preview_swap_exact_input(
+        wallet,
+        config,
+        amount_in,
+        cycle.token_in,
+        amount_out_min,
+        cycle.cycle_ids.clone(),
+        999999999,
+        Some(TxPolicies::default()),
+    )
+    .await?;
+    Ok(response.gas_used)
+}
+
+// Input and minimum output for the swap script. `swap_amounts` is the script's own quote, so
+// its final amount is exactly what `get_amounts_out` will compute against unchanged reserves.
+fn swap_limits(cycle: &NetPositiveCycle) -> (u64, u64) {
+    let amount_in: u64 = scale_and_convert_to_u64_from_u256(cycle.optimal_in, 1).unwrap();
+    let amount_out_min: u64 =
+        scale_and_convert_to_u64_from_u256(*cycle.swap_amounts.last().unwrap(), 1).unwrap();
+    (amount_in, amount_out_min)
 }
+
 fn scale_and_convert_to_u64_from_u256(value: U256, scale: u64) -> Option<u64> {
     // Convert scale to U256
     let scaling_factor = U256::from(scale);
@@ -46,26 +205,73 @@ fn scale_and_convert_to_u64_from_u256(value: U256, scale: u64) -> Option<u64> {
     let scaled_value = value / scaling_factor;
 
     // Check if the scaled value fits within u64
-    if scaled_value <= U256::from(u64::MAX) { 
+    if scaled_value <= U256::from(u64::MAX) {
         Some(scaled_value.low_u64()) // Extract lower 64 bits
     } else {
         None // Indicate out of bounds
     }
 }
-pub fn scale_and_convert_to_u64(value: I256, scale: i64) -> i64 {
-    // Ensure the scaling factor is positive
-    let scaling_factor = I256::from(scale);
-
-    // Perform scaling (integer division)
-    let scaled_value = value / scaling_factor;
-
-    scaled_value.low_i64()
-}
 abigen!(Script(
     name = "SwapScript",
-    abi = "/Users/walterphillips/BlockchainDev/triton/swap-script/out/debug/swap-script-abi.json"
+    abi = "src/contracts/swap_exact_input_script-abi.json"
 ));
 
+#[derive(Debug)]
+pub enum ScriptError {
+    Read(String),
+    Write(String),
+    HashMismatch { expected: String, actual: String },
+}
+
+// The swap script the bindings were generated for, built in so the bot runs from anywhere
+const SWAP_SCRIPT_BINARY: &[u8] = include_bytes!("contracts/swap_exact_input_script.bin");
+
+/// The swap script binary, read from `config.script_path` only when one is set
+pub fn script_binary(config: &Config) -> std::result::Result<Cow<'static, [u8]>, ScriptError> {
+    match &config.script_path {
+        Some(path) => std::fs::read(path)
+            .map(Cow::Owned)
+            .map_err(|e| ScriptError::Read(format!("{}: {}", path, e))),
+        None => Ok(Cow::Borrowed(SWAP_SCRIPT_BINARY)),
+    }
+}
+
+/// A checked copy of the swap script in a directory only we can read, removed on drop
+#[derive(Debug)]
+pub struct PreparedScript {
+    pub path: PathBuf,
+    _dir: TempDir,
+}
+
+/// Checks the swap script binary is the one the bindings were generated for, so a stale or
+/// rebuilt script is caught before anything is sent. The bindings only load scripts from a
+/// file, so it is then written to a fresh 0700 directory and the written copy checked again.
+pub fn prepare_script(config: &Config) -> std::result::Result<PreparedScript, ScriptError> {
+    let binary = script_binary(config)?;
+    check_script_hash(&binary, &config.script_sha256)?;
+
+    let dir = tempfile::Builder::new()
+        .prefix("triton-")
+        .tempdir()
+        .map_err(|e| ScriptError::Write(e.to_string()))?;
+    let path = dir.path().join("swap_exact_input_script.bin");
+    std::fs::write(&path, &binary)
+        .map_err(|e| ScriptError::Write(format!("{}: {}", path.display(), e)))?;
+    let written = std::fs::read(&path)
+        .map_err(|e| ScriptError::Read(format!("{}: {}", path.display(), e)))?;
+    check_script_hash(&written, &config.script_sha256)?;
+    Ok(PreparedScript { path, _dir: dir })
+}
+
+fn check_script_hash(binary: &[u8], expected: &str) -> std::result::Result<(), ScriptError> {
+    let actual = format!("{:x}", Hasher::hash(binary));
+    let expected = expected.trim_start_matches("0x").to_lowercase();
+    if actual != expected {
+        return Err(ScriptError::HashMismatch { expected, actual });
+    }
+    Ok(())
+}
+
 pub async fn get_transaction_inputs_outputs(
     wallet: &WalletUnlocked,
     assets: &Vec<(AssetId, u64)>,
@@ -88,29 +294,89 @@ pub async fn get_transaction_inputs_outputs(
     (inputs, outputs)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn preview_swap_exact_input(
     wallet: &WalletUnlocked,
+    config: &Config,
     amount_in: u64,
     asset_in: AssetId,
     amount_out_min: u64,
     pools: Vec<PoolId>,
     deadline: u32,
     tx_policies: Option<TxPolicies>,
-) -> Result<Vec<(u64, AssetId)>> {
-    let amm_contract: MiraAmmContract<WalletUnlocked> =
-        MiraAmmContract::new(ContractId::from_str(CONTRACT_ID).unwrap(), wallet.clone());
-    let swap_exact_input_script = SwapScript::new(
-        wallet.clone(),
-        "/Users/walterphillips/BlockchainDev/triton/swap-script/out/debug/swap-script.bin",
+) -> Result<CallResponse<Vec<(u64, AssetId)>>> {
+    let response = swap_exact_input(
+        wallet,
+        config,
+        amount_in,
+        asset_in,
+        amount_out_min,
+        pools,
+        deadline,
+        tx_policies,
     )
-    .with_configurables(
+    .await?
+    .simulate(Execution::Realistic)
+    .await?;
+    println!("assets: {:#?}", response.value);
+    Ok(response)
+}
+
+/// Sends the swap script. The transaction id is known from here on, the receipts once
+/// `response` is awaited.
+#[allow(clippy::too_many_arguments)]
+pub async fn submit_swap_exact_input(
+    wallet: &WalletUnlocked,
+    config: &Config,
+    amount_in: u64,
+    asset_in: AssetId,
+    amount_out_min: u64,
+    pools: Vec<PoolId>,
+    deadline: u32,
+    tx_policies: Option<TxPolicies>,
+) -> Result<SubmitResponse<WalletUnlocked, Vec<(u64, AssetId)>, ScriptCall>> {
+    swap_exact_input(
+        wallet,
+        config,
+        amount_in,
+        asset_in,
+        amount_out_min,
+        pools,
+        deadline,
+        tx_policies,
+    )
+    .await?
+    .submit()
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn swap_exact_input(
+    wallet: &WalletUnlocked,
+    config: &Config,
+    amount_in: u64,
+    asset_in: AssetId,
+    amount_out_min: u64,
+    pools: Vec<PoolId>,
+    deadline: u32,
+    tx_policies: Option<TxPolicies>,
+) -> Result<CallHandler<WalletUnlocked, ScriptCall, Vec<(u64, AssetId)>>> {
+    let amm_contract: MiraAmmContract<WalletUnlocked> =
+        MiraAmmContract::new(config.contract_id(), wallet.clone());
+    // Points at the copy `prepare_script` checked once it has run
+    let Some(script_path) = &config.script_path else {
+        return Err(Error::Other(
+            "swap script not prepared, run bundle::prepare_script first".to_string(),
+        ));
+    };
+    let swap_exact_input_script = SwapScript::new(wallet.clone(), script_path).with_configurables(
         SwapScriptConfigurables::default()
             .with_AMM_CONTRACT_ID(amm_contract.contract_id().into())
             .unwrap(),
     );
     let (inputs, outputs) =
         get_transaction_inputs_outputs(wallet, &vec![(asset_in, amount_in)]).await;
-    let assets = swap_exact_input_script
+    Ok(swap_exact_input_script
         .main(
             amount_in,
             asset_in,
@@ -123,11 +389,5 @@ pub async fn preview_swap_exact_input(
         .with_contracts(&[&amm_contract])
         .with_inputs(inputs)
         .with_outputs(outputs)
-        .with_variable_output_policy(VariableOutputPolicy::Exactly(1))
-        .simulate(Execution::Realistic)
-        .await
-        .unwrap()
-        .value;
-    println!("assets: {:#?}", assets);
-    Ok(assets)
+        .with_variable_output_policy(VariableOutputPolicy::Exactly(1)))
 }
@@ -1,17 +1,39 @@
 use fuels::types::AssetId;
 use log::debug;
 use serde::Deserialize;
-use std::{cell::RefCell, cmp::Ordering, str::FromStr};
+use std::{cell::RefCell, cmp::Ordering, collections::HashMap};
 // use alloy_primitives::I256;
-use crate::{bundle::scale_and_convert_to_u64, types::Pool};
+use crate::{
+    constants::BASIS_POINTS_DENOMINATOR,
+    graph::start_at_base_asset,
+    script_math,
+    triton::{Cycle, Triton},
+    types::Pool,
+};
 use ethers::types::{I256, U256};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct NetPositiveCycle {
+    pub token_in: AssetId,
     pub profit: I256,
     pub optimal_in: U256,
     pub swap_amounts: Vec<U256>,
     pub cycle_ids: Vec<(AssetId, AssetId, bool)>,
+    pub search: SearchDiagnostics,
+}
+
+/// How the optimal input of a cycle was found
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+pub struct SearchDiagnostics {
+    // Largest input considered, from the reserves along the cycle and our balance
+    pub upper_bound: U256,
+    // Profit function calls, zero when the closed form applied
+    pub evaluations: usize,
+    // Golden section steps taken after bracketing
+    pub iterations: usize,
+    // Width of the final bracket around the optimum
+    pub bracket_width: U256,
+    pub converged: bool,
 }
 
 impl Ord for NetPositiveCycle {
@@ -35,101 +57,369 @@ impl PartialEq for NetPositiveCycle {
     }
 }
 
-pub fn find_optimal_cycles(triton: &mut crate::triton::Triton) -> Vec<NetPositiveCycle> {
-    let usdc_asset_id =
-    AssetId::from_str("0x286c479da40dc953bddc3bb4c453b608bba2e0ac483b077bd475174115395e6b")
-        .unwrap();
-    let fuel_asset_id =
-    AssetId::from_str("0x1d5d97005e41cae2187a895fd8eab0506111e0e2f3331cd3912c15c24e3c1d82")
-        .unwrap();
-
-    let mut net_profit_cycles = Vec::new();
-    for cycle in &triton.cycles {
-        debug!("cycle: {:#?}", cycle);
-        let pairs = cycle
-            .cycle
-            .iter()
-            .filter_map(|pair| triton.pools.get(&pair.index))
-            .collect::<Vec<&RefCell<Pool>>>();
-        let pairs_clone = pairs.clone();
-        log::debug!("getting profit");
-        let profit_function =
-            move |amount_in: U256| -> I256 { get_profit(fuel_asset_id, amount_in, &pairs_clone) };
-
-        log::debug!("maximizing profit");
-        let optimal = maximize_profit(
-            U256::from("1"),
-            U256::from_dec_str("10000000000000000000000").unwrap(),
-            U256::from_dec_str("10").unwrap(),
-            profit_function,
-        );
+// Inputs sampled on a halving grid below the upper bound to bracket the optimum
+const BRACKET_SAMPLES: usize = 64;
+// Cap on golden section steps once the optimum is bracketed
+const MAX_SEARCH_ITERATIONS: usize = 128;
+// Search stops once the bracket is narrower than upper_bound / SEARCH_PRECISION
+const SEARCH_PRECISION: u64 = 1_000_000_000;
+
+/// Last evaluation of every tracked cycle. After an event only the cycles routing through
+/// the pool it touched are repriced, the rest keep their previous result.
+#[derive(Debug, Default)]
+pub struct OpportunityCache {
+    // Cycle index to its most profitable direction at the reserves it was last priced on
+    evaluated: HashMap<usize, NetPositiveCycle>,
+    // Wallet balances capping the input of cycles starting at each asset
+    balances: HashMap<AssetId, U256>,
+}
 
-        log::debug!("getting profit with amount");
-        let (profit, swap_amounts) = get_profit_with_amount(fuel_asset_id, optimal, &pairs);
-        let mut cycle_internal = Vec::new();
-        for pair in pairs {
-            let is_stable = pair.borrow().fee_rate < U256::from(300);
-            cycle_internal.push((pair.borrow().from, pair.borrow().to, is_stable));
+impl OpportunityCache {
+    /// Caps inputs for cycles starting at `asset`, taking effect as they are next repriced
+    pub fn set_balance(&mut self, asset: AssetId, balance: U256) {
+        self.balances.insert(asset, balance);
+    }
+
+    /// Prices every cycle, used once reserves have been synced
+    pub fn refresh_all(&mut self, triton: &Triton) {
+        for cycle_index in 0..triton.cycles.len() {
+            self.refresh_cycle(triton, cycle_index);
         }
-        println!("profit: {}", scale_and_convert_to_u64(profit,1000000));
-        if profit > I256::one() {
-            let net_positive_cycle = NetPositiveCycle {
-                profit,
-                optimal_in: optimal,
-                cycle_ids: cycle_internal,
-                swap_amounts,
-            };
-            net_profit_cycles.push(net_positive_cycle);
+    }
+
+    /// Reprices the cycles through the pool at `pool_index`, returning how many were repriced
+    pub fn refresh_pool(&mut self, triton: &Triton, pool_index: usize) -> usize {
+        let Some(cycle_indices) = triton.cycles_by_pool.get(&pool_index) else {
+            return 0;
+        };
+        for cycle_index in cycle_indices {
+            self.refresh_cycle(triton, *cycle_index);
         }
+        cycle_indices.len()
+    }
+
+    pub fn refresh_cycle(&mut self, triton: &Triton, cycle_index: usize) {
+        let cycle = &triton.cycles[cycle_index];
+        let balance = self.balances.get(&cycle.start).copied();
+        self.evaluated
+            .insert(cycle_index, evaluate_best_direction(triton, cycle, balance));
+    }
+
+    /// Every cycle that was profitable as last priced, most raw profit first. Raw units
+    /// differ between start assets, so cycles are only comparable once `Valuation` prices them.
+    pub fn ranked(&self) -> Vec<NetPositiveCycle> {
+        let mut net_profit_cycles = self
+            .evaluated
+            .values()
+            .filter(|cycle| cycle.profit > I256::one())
+            .cloned()
+            .collect::<Vec<NetPositiveCycle>>();
+        net_profit_cycles.sort();
+        net_profit_cycles
+    }
+}
+
+// Cycles are stored once per loop, so price both directions and keep the better one
+fn evaluate_best_direction(
+    triton: &Triton,
+    cycle: &Cycle,
+    balance: Option<U256>,
+) -> NetPositiveCycle {
+    let forward = evaluate_cycle(triton, cycle, balance);
+    let backward = evaluate_cycle(triton, &cycle.reversed(), balance);
+    if backward.profit > forward.profit {
+        backward
+    } else {
+        forward
     }
-    debug!("net_profit_cycles: {:#?}", net_profit_cycles);
-    net_profit_cycles.sort();
-    net_profit_cycles.into_iter().take(5).collect()
 }
 
-fn maximize_profit(
-    mut domain_min: U256,
-    mut domain_max: U256,
-    lowest_delta: U256,
-    f: impl Fn(U256) -> I256,
+/// Finds the most profitable input for `cycle` at current reserves, starting from `cycle.start`
+/// and spending at most `balance` of it when given
+pub fn evaluate_cycle(triton: &Triton, cycle: &Cycle, balance: Option<U256>) -> NetPositiveCycle {
+    let token_in = cycle.start;
+    let pairs = cycle
+        .cycle
+        .iter()
+        .filter_map(|pair| triton.pools.get(&pair.index))
+        .collect::<Vec<&RefCell<Pool>>>();
+    let pairs_clone = pairs.clone();
+    log::debug!("getting profit");
+    let profit_function =
+        move |amount_in: U256| -> I256 { get_profit(token_in, amount_in, &pairs_clone) };
+
+    log::debug!("maximizing profit");
+    let upper_bound = input_upper_bound(token_in, &pairs, balance);
+    let (optimal, search) = match optimal_in_volatile(token_in, &pairs) {
+        // Profit is concave for volatile cycles, so clamping the optimum keeps it optimal
+        Some(optimal) => (
+            optimal.min(upper_bound),
+            SearchDiagnostics {
+                upper_bound,
+                converged: true,
+                ..Default::default()
+            },
+        ),
+        // Stable pools have no closed form, fall back to searching
+        None => maximize_profit(upper_bound, profit_function),
+    };
+    if !search.converged {
+        debug!("Search did not converge for {:?}: {:?}", cycle, search);
+    }
+
+    log::debug!("getting profit with amount");
+    // Hops are quoted with the swap script's arithmetic, so these are the amounts it will see
+    let (profit, swap_amounts) = get_profit_with_amount(token_in, optimal, &pairs);
+    let mut cycle_internal = Vec::new();
+    for pair in pairs {
+        cycle_internal.push(pair.borrow().pool_id());
+    }
+    NetPositiveCycle {
+        token_in,
+        profit,
+        optimal_in: optimal,
+        cycle_ids: cycle_internal,
+        swap_amounts,
+        search,
+    }
+}
+
+/// Largest input worth considering for a cycle: no hop can absorb more than its input reserve,
+/// measured back in `token_in` through the marginal rates before it, and never more than
+/// `balance` when given
+pub fn input_upper_bound(
+    token_in: AssetId,
+    pairs: &[&RefCell<Pool>],
+    balance: Option<U256>,
 ) -> U256 {
-    let mut best_amount = domain_min;
-    let mut best_profit = f(domain_min);
-
-    loop {
-        if domain_max > domain_min {
-            if (domain_max - domain_min) > lowest_delta {
-                let mid = (domain_min + domain_max) / 2;
-                let lower_mid = (mid + domain_min) / 2;
-                let upper_mid = (mid + domain_max) / 2;
-
-                let f_output_lower = f(lower_mid);
-                let f_output_upper = f(upper_mid);
-
-                // Store best result seen so far
-                if f_output_lower > best_profit {
-                    best_profit = f_output_lower;
-                    best_amount = lower_mid;
-                }
-                if f_output_upper > best_profit {
-                    best_profit = f_output_upper;
-                    best_amount = upper_mid;
-                }
-
-                if f_output_lower > f_output_upper {
-                    domain_max = mid;
-                } else {
-                    domain_min = mid;
-                }
-            } else {
-                break;
+    let mut token = token_in;
+    // Units of `token` received per unit of `token_in` at the margin so far
+    let mut cumulative_rate = 1.0;
+    let mut bound = f64::MAX;
+
+    for pair in pairs {
+        let pool = pair.borrow();
+        let (reserve_in, token_out) = if token == pool.from {
+            (pool.reserve_0, pool.to)
+        } else {
+            (pool.reserve_1, pool.from)
+        };
+        let Some(rate) = marginal_rate(&pool, token) else {
+            return U256::zero();
+        };
+        bound = bound.min(u256_to_f64(reserve_in) / cumulative_rate);
+        cumulative_rate *= rate;
+        token = token_out;
+    }
+
+    let bound = if bound >= u128::MAX as f64 {
+        U256::from(u128::MAX)
+    } else {
+        U256::from(bound as u128)
+    };
+    match balance {
+        Some(balance) => bound.min(balance),
+        None => bound,
+    }
+}
+
+/// Profit maximising input for a cycle made only of volatile pools, or None if any hop is
+/// stable. The hops are folded into one virtual x·y = k pool with reserves (e_in, e_out)
+/// charging the first hop's fee γ, whose optimum is (sqrt(γ·e_in·e_out) - e_in) / γ.
+/// Returns zero when the loop can't profit at any size. The optimum is rounded down, so
+/// pricing it through `get_profit_with_amount` agrees with the search to within rounding.
+pub fn optimal_in_volatile(token_in: AssetId, pairs: &[&RefCell<Pool>]) -> Option<U256> {
+    let denominator = U256::from(BASIS_POINTS_DENOMINATOR);
+    let mut token = token_in;
+    // Virtual reserves of the hops folded so far, and the fee multiplier of the first hop
+    let mut virtual_pool: Option<(U256, U256, U256)> = None;
+
+    for pair in pairs {
+        let pool = pair.borrow();
+        if pool.is_stable {
+            return None;
+        }
+        let (reserve_in, reserve_out, token_out) = if token == pool.from {
+            (pool.reserve_0, pool.reserve_1, pool.to)
+        } else {
+            (pool.reserve_1, pool.reserve_0, pool.from)
+        };
+        if reserve_in.is_zero() || reserve_out.is_zero() {
+            return Some(U256::zero());
+        }
+        let gamma = denominator - U256::from(pool.fees.total(false));
+
+        virtual_pool = Some(match virtual_pool {
+            None => (reserve_in, reserve_out, gamma),
+            Some((e_in, e_out, first_gamma)) => {
+                let joined = reserve_in * denominator + gamma * e_out;
+                (
+                    e_in * reserve_in * denominator / joined,
+                    gamma * e_out * reserve_out / joined,
+                    first_gamma,
+                )
             }
+        });
+        token = token_out;
+    }
+
+    let Some((e_in, e_out, gamma)) = virtual_pool else {
+        return Some(U256::zero());
+    };
+    // Unprofitable even for an infinitesimal input
+    if gamma * e_out <= e_in * denominator {
+        return Some(U256::zero());
+    }
+    let root = (gamma * e_in * e_out * denominator).integer_sqrt();
+    Some((root - e_in * denominator) / gamma)
+}
+
+/// Arbitrage loops of any length across every tracked pool, found as negative cycles of
+/// -ln(marginal rate). Catches loops the hop-limited startup search from base assets misses.
+/// Loops are entered at a base asset, since that's what the bot holds, and dropped if they
+/// touch none.
+pub fn find_negative_cycles(triton: &Triton) -> Vec<Cycle> {
+    triton
+        .graph
+        .find_negative_cycles(|token_in, edge| {
+            let pool = triton.pools.get(&edge.pair.index)?.borrow();
+            let rate = marginal_rate(&pool, *token_in)?;
+            Some(-rate.ln())
+        })
+        .into_iter()
+        .filter_map(|cycle| start_at_base_asset(cycle, &triton.base_assets))
+        .collect()
+}
+
+/// Output per unit of input for an infinitesimal swap of `token_in` through `pool`, net of
+/// fees and in raw units of each asset. None while either reserve is empty.
+pub fn marginal_rate(pool: &Pool, token_in: AssetId) -> Option<f64> {
+    let fee = pool.fees.total(pool.is_stable) as f64 / BASIS_POINTS_DENOMINATOR as f64;
+    Some(mid_rate(pool, token_in)? * (1.0 - fee))
+}
+
+/// Price of one raw unit of `token_in` in raw units of the other asset, before fees
+pub fn mid_rate(pool: &Pool, token_in: AssetId) -> Option<f64> {
+    let (reserve_in, reserve_out, decimals_in, decimals_out) = if token_in == pool.from {
+        (
+            pool.reserve_0,
+            pool.reserve_1,
+            pool.decimals_0,
+            pool.decimals_1,
+        )
+    } else {
+        (
+            pool.reserve_1,
+            pool.reserve_0,
+            pool.decimals_1,
+            pool.decimals_0,
+        )
+    };
+    if reserve_in.is_zero() || reserve_out.is_zero() {
+        return None;
+    }
+
+    let rate = if pool.is_stable {
+        // Slope of x³y + y³x = k on decimal adjusted reserves, scaled back to raw units
+        let x = u256_to_f64(reserve_in) / 10f64.powi(decimals_in as i32);
+        let y = u256_to_f64(reserve_out) / 10f64.powi(decimals_out as i32);
+        let slope = (3.0 * x * x * y + y * y * y) / (x * x * x + 3.0 * x * y * y);
+        slope * 10f64.powi(decimals_out as i32 - decimals_in as i32)
+    } else {
+        u256_to_f64(reserve_out) / u256_to_f64(reserve_in)
+    };
+    Some(rate)
+}
+
+pub fn u256_to_f64(value: U256) -> f64 {
+    value
+        .0
+        .iter()
+        .rev()
+        .fold(0.0, |acc, limb| acc * 2f64.powi(64) + *limb as f64)
+}
+
+/// Maximises `f` over inputs up to `upper_bound`. A halving grid below the bound brackets the
+/// best sample first, so an optimum many orders of magnitude below the bound is still found,
+/// then golden section search narrows the bracket until it is within `SEARCH_PRECISION` of the
+/// bound or `MAX_SEARCH_ITERATIONS` steps have been taken.
+pub fn maximize_profit(upper_bound: U256, f: impl Fn(U256) -> I256) -> (U256, SearchDiagnostics) {
+    let mut diagnostics = SearchDiagnostics {
+        upper_bound,
+        ..Default::default()
+    };
+    let mut evaluate = |amount: U256| {
+        diagnostics.evaluations += 1;
+        f(amount)
+    };
+
+    // Ascending samples at upper_bound / 2^k
+    let mut samples = Vec::new();
+    let mut amount = upper_bound;
+    while !amount.is_zero() && samples.len() < BRACKET_SAMPLES {
+        samples.push((amount, evaluate(amount)));
+        amount /= 2;
+    }
+    samples.reverse();
+    let Some(best) = (0..samples.len()).max_by_key(|i| samples[*i].1) else {
+        diagnostics.converged = true;
+        return (U256::zero(), diagnostics);
+    };
+    let (mut best_amount, mut best_profit) = samples[best];
+
+    let mut low = if best == 0 {
+        U256::zero()
+    } else {
+        samples[best - 1].0
+    };
+    let mut high = samples.get(best + 1).map_or(upper_bound, |sample| sample.0);
+    let golden = |width: U256| width * U256::from(618_034) / U256::from(1_000_000);
+    let tolerance = (upper_bound / SEARCH_PRECISION).max(U256::one());
+
+    let mut lower_mid = high - golden(high - low);
+    let mut upper_mid = low + golden(high - low);
+    let mut f_lower = evaluate(lower_mid);
+    let mut f_upper = evaluate(upper_mid);
+    let mut iterations = 0;
+
+    while high - low > tolerance && iterations < MAX_SEARCH_ITERATIONS {
+        iterations += 1;
+        // Store best result seen so far
+        if f_lower > best_profit {
+            best_profit = f_lower;
+            best_amount = lower_mid;
+        }
+        if f_upper > best_profit {
+            best_profit = f_upper;
+            best_amount = upper_mid;
+        }
+
+        if f_lower >= f_upper {
+            high = upper_mid;
+            upper_mid = lower_mid;
+            f_upper = f_lower;
+            lower_mid = high - golden(high - low);
+            f_lower = evaluate(lower_mid);
         } else {
-            break;
+            low = lower_mid;
+            lower_mid = upper_mid;
+            f_lower = f_upper;
+            upper_mid = low + golden(high - low);
+            f_upper = evaluate(upper_mid);
         }
     }
+    if f_lower > best_profit {
+        best_amount = lower_mid;
+        best_profit = f_lower;
+    }
+    if f_upper > best_profit {
+        best_amount = upper_mid;
+    }
 
-    best_amount
+    diagnostics.iterations = iterations;
+    diagnostics.bracket_width = high - low;
+    diagnostics.converged = high - low <= tolerance;
+    (best_amount, diagnostics)
 }
 
 pub fn get_profit_with_amount(
@@ -143,50 +433,23 @@ pub fn get_profit_with_amount(
 
     for (i, pair) in pairs.iter().enumerate() {
         let pair = pair.borrow();
-        let fees = pair.fee_rate;
-        let (reserve0, reserve1) = if pair.to == token_in {
-            (pair.reserve_0, pair.reserve_1)
-        } else {
-            (pair.reserve_1, pair.reserve_0)
-        };
 
         debug!(
-            "Swap {}: amount_in={}, reserves=({}, {}), fees={}, token_in={}",
+            "Swap {}: amount_in={}, pool={}, stable={}, token_in={}",
             i + 1,
             amount_out,
-            reserve0,
-            reserve1,
-            fees,
+            pair.pool_name,
+            pair.is_stable,
             token_in
         );
 
-        amount_out = get_amount_out_with_saturation(amount_out, reserve0, reserve1, fees);
+        (amount_out, token_in) = get_amount_out_for_pool(&pair, token_in, amount_out);
         amounts.push(amount_out);
 
         debug!("Swap {} output: {}", i + 1, amount_out);
-
-        token_in = if pair.to == token_in {
-            pair.from
-        } else {
-            pair.to
-        };
     }
 
-    let profit = if amount_out >= amount_in {
-        let profit = amount_out.saturating_sub(amount_in);
-        if let Ok(i256_value) = I256::try_from(profit) {
-            i256_value
-        } else {
-            I256::MAX
-        }
-    } else {
-        let loss = amount_in.saturating_sub(amount_out);
-        if let Ok(i256_value) = I256::try_from(loss) {
-            -i256_value
-        } else {
-            I256::MIN
-        }
-    };
+    let profit = net_profit(amount_in, amount_out);
 
     debug!(
         "Profit calculation with amounts: amounts={:?}, profit={}",
@@ -200,33 +463,24 @@ pub fn get_profit(token_in: AssetId, amount_in: U256, pairs: &Vec<&RefCell<Pool>
     let mut token_in = token_in;
     let mut amounts = vec![amount_in]; // Track all amounts through the cycle
 
-    for (_, pair) in pairs.iter().enumerate() {
+    for pair in pairs.iter() {
         let pair = pair.borrow();
-        let fees = pair.fee_rate;
-        let (reserve0, reserve1) = if pair.to == token_in {
-            (pair.reserve_0, pair.reserve_1)
-        } else {
-            (pair.reserve_1, pair.reserve_0)
-        };
-
-        // println!(
-        //     "Swap {}: amount_in={}, reserves=({}, {}), fees={}, token_in={}",
-        //     i + 1, amount_out, reserve0, reserve1, fees, token_in
-        // );
-
-        amount_out = get_amount_out_with_saturation(amount_out, reserve0, reserve1, fees);
+        (amount_out, token_in) = get_amount_out_for_pool(&pair, token_in, amount_out);
         amounts.push(amount_out);
+    }
 
-        // println!("Swap {} output: {}", i + 1, amount_out);
+    let profit = net_profit(amount_in, amount_out);
 
-        token_in = if pair.to == token_in {
-            pair.from
-        } else {
-            pair.to
-        };
-    }
+    debug!(
+        "Profit calculation: amounts={:?}, profit={}",
+        amounts, profit
+    );
+    profit
+}
 
-    let profit = if amount_out >= amount_in {
+/// `amount_out - amount_in` as a signed value, saturating at the I256 bounds
+pub fn net_profit(amount_in: U256, amount_out: U256) -> I256 {
+    if amount_out >= amount_in {
         let profit = amount_out.saturating_sub(amount_in);
         if let Ok(i256_value) = I256::try_from(profit) {
             i256_value
@@ -240,70 +494,127 @@ pub fn get_profit(token_in: AssetId, amount_in: U256, pairs: &Vec<&RefCell<Pool>
         } else {
             I256::MIN
         }
-    };
+    }
+}
 
-    debug!(
-        "Profit calculation: amounts={:?}, profit={}",
-        amounts, profit
-    );
-    profit
+/// Quotes a single hop through `pool`, returning the amount out and the asset received.
+/// Uses the swap script's own arithmetic, so a hop the script would revert on yields nothing.
+pub fn get_amount_out_for_pool(pool: &Pool, token_in: AssetId, amount_in: U256) -> (U256, AssetId) {
+    let token_out = if token_in == pool.from {
+        pool.to
+    } else {
+        pool.from
+    };
+    let amount_out = (amount_in <= U256::from(u64::MAX))
+        .then(|| amount_in.low_u64())
+        .and_then(|amount_in| script_math::get_amount_out_for_hop(pool, token_in, amount_in))
+        .map_or(U256::zero(), |(amount_out, _)| U256::from(amount_out));
+    (amount_out, token_out)
 }
 
-pub fn get_amount_out(
-    a_in: U256,
-    reserve_in: U256,
-    reserve_out: U256,
-    fees: U256,
-    router_fee: U256,
-) -> U256 {
-    if a_in == U256::zero() {
-        return U256::zero();
-    }
-    let a_in_with_fee = a_in.saturating_mul(router_fee);
-    let a_out = a_in_with_fee.saturating_mul(reserve_out)
-        / U256::from(10000)
-            .saturating_mul(reserve_in)
-            .saturating_add(a_in_with_fee);
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
 
-    a_out - a_out.saturating_mul(fees) / U256::from(10000)
-}
-fn get_amount_out_with_saturation(
-    amount_in: U256,
-    reserve_in: U256,
-    reserve_out: U256,
-    fee_rate: U256,
-) -> U256 {
-    if amount_in == U256::zero() || reserve_in == U256::zero() || reserve_out == U256::zero() {
-        return U256::zero();
+    use ethers::types::{I256, U256};
+    use fuels::types::AssetId;
+
+    use super::{
+        get_profit, input_upper_bound, maximize_profit, optimal_in_volatile, NetPositiveCycle,
+        OpportunityCache, SearchDiagnostics,
+    };
+    use crate::{tokens::default_fees, types::Pool};
+
+    fn asset(index: u8) -> AssetId {
+        AssetId::new([index + 1; 32])
     }
 
-    // Scale up the calculations to preserve precision
-    let scale = U256::from(1_000_000); // Use 1M as scaling factor
-    let fee_denominator = U256::from(100000);
+    fn volatile_pool(from: u8, to: u8, reserve_0: u64, reserve_1: u64) -> RefCell<Pool> {
+        RefCell::new(Pool {
+            pool_name: format!("{}/{}", from, to),
+            from: asset(from),
+            to: asset(to),
+            is_stable: false,
+            reserve_0: U256::from(reserve_0),
+            reserve_1: U256::from(reserve_1),
+            decimals_0: 9,
+            decimals_1: 9,
+            fees: default_fees(),
+        })
+    }
 
-    // Calculate fee with scaling
-    let scaled_amount = amount_in.saturating_mul(scale);
-    let amount_fee = scaled_amount.saturating_mul(fee_rate) / fee_denominator;
-    let effective_amount_in = scaled_amount.saturating_sub(amount_fee);
+    // The closed form optimum has to price at least as well as searching through the swap
+    // arithmetic, give or take a unit of rounding per hop
+    fn assert_matches_search(pools: &[RefCell<Pool>]) {
+        let pairs = pools.iter().collect::<Vec<&RefCell<Pool>>>();
+        let closed = optimal_in_volatile(asset(0), &pairs).unwrap();
+        let upper_bound = input_upper_bound(asset(0), &pairs, None);
+        let (searched, _) =
+            maximize_profit(upper_bound, |amount| get_profit(asset(0), amount, &pairs));
+
+        let closed_profit = get_profit(asset(0), closed, &pairs);
+        let searched_profit = get_profit(asset(0), searched, &pairs);
+        assert!(closed_profit > I256::zero());
+        assert!(
+            closed_profit >= searched_profit - I256::from(pairs.len() as i64),
+            "closed form {} makes {}, search {} makes {}",
+            closed,
+            closed_profit,
+            searched,
+            searched_profit
+        );
+    }
 
-    // Calculate output with scaling
-    let numerator = effective_amount_in.saturating_mul(reserve_out);
-    let denominator = reserve_in
-        .saturating_mul(scale)
-        .saturating_add(effective_amount_in);
+    #[test]
+    fn two_hop_optimum_matches_search() {
+        assert_matches_search(&[
+            volatile_pool(0, 1, 1_000_000_000_000, 2_000_000_000_000),
+            volatile_pool(1, 0, 1_000_000_000_000, 550_000_000_000),
+        ]);
+    }
 
-    if denominator == U256::zero() {
-        return U256::zero();
+    #[test]
+    fn three_hop_optimum_matches_search() {
+        assert_matches_search(&[
+            volatile_pool(0, 1, 1_000_000_000_000, 2_000_000_000_000),
+            volatile_pool(1, 2, 2_000_000_000_000, 3_000_000_000_000),
+            volatile_pool(2, 0, 3_000_000_000_000, 1_100_000_000_000),
+        ]);
     }
 
-    // Unscale the result
-    
+    #[test]
+    fn unprofitable_cycle_has_zero_optimum() {
+        let pools = [
+            volatile_pool(0, 1, 1_000_000_000_000, 1_000_000_000_000),
+            volatile_pool(1, 0, 1_000_000_000_000, 1_000_000_000_000),
+        ];
+        let pairs = pools.iter().collect::<Vec<&RefCell<Pool>>>();
+        assert_eq!(optimal_in_volatile(asset(0), &pairs), Some(U256::zero()));
+    }
 
-    // Add debug logging
-    // println!(
-    //     "Swap calculation: amount_in={}, effective_amount={}, result={}",
-    //     amount_in, effective_amount_in, result
-    // );
+    // Profits in raw units of different assets can't be compared, so none may be dropped
+    // before `Valuation` prices them
+    #[test]
+    fn ranked_keeps_every_profitable_cycle() {
+        let mut opportunities = OpportunityCache::default();
+        for index in 0..8u8 {
+            opportunities.evaluated.insert(
+                index as usize,
+                NetPositiveCycle {
+                    token_in: asset(index),
+                    profit: I256::from(index as i64),
+                    optimal_in: U256::one(),
+                    swap_amounts: Vec::new(),
+                    cycle_ids: Vec::new(),
+                    search: SearchDiagnostics::default(),
+                },
+            );
+        }
 
-    numerator / denominator
+        let ranked = opportunities.ranked();
+        // Profits of zero and one aren't worth a transaction
+        assert_eq!(ranked.len(), 6);
+        assert_eq!(ranked[0].profit, I256::from(7));
+        assert_eq!(ranked[5].profit, I256::from(2));
+    }
 }
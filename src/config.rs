@@ -0,0 +1,191 @@
+use std::{env, fs, path::Path, str::FromStr};
+
+use ethers::types::U256;
+use fuels::types::{AssetId, ContractId};
+
+use crate::{
+    constants::{CONTRACT_ID, SWAP_SCRIPT_SHA256},
+    tokens::{default_fees, get_base_assets, get_gas_asset, get_numeraire, get_pools},
+    types::{Config, Pool},
+};
+
+// Read when neither `--config` nor `TRITON_CONFIG` names a file
+const DEFAULT_CONFIG_PATH: &str = "triton.toml";
+// Fuel Ignition mainnet
+const MAINNET_CHAIN_ID: u64 = 9889;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(String),
+    Parse(String),
+    Invalid(String),
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            node_url: "mainnet.fuel.network".to_string(),
+            chain_id: MAINNET_CHAIN_ID,
+            contract_id: CONTRACT_ID.to_string(),
+            script_path: None,
+            script_sha256: SWAP_SCRIPT_SHA256.to_string(),
+            pools: Vec::new(),
+            base_assets: Vec::new(),
+            max_hops: 5,
+            numeraire: format!("{:#x}", get_numeraire()),
+            gas_asset: format!("{:#x}", get_gas_asset()),
+            min_net_profit: 0.0,
+            max_input_value: None,
+            dry_run: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn contract_id(&self) -> ContractId {
+        ContractId::from_str(&self.contract_id).expect("contract id checked on load")
+    }
+
+    pub fn numeraire(&self) -> AssetId {
+        parse_asset(&self.numeraire).expect("numeraire checked on load")
+    }
+
+    pub fn gas_asset(&self) -> AssetId {
+        parse_asset(&self.gas_asset).expect("gas asset checked on load")
+    }
+
+    pub fn base_assets(&self) -> Vec<AssetId> {
+        if self.base_assets.is_empty() {
+            return get_base_assets();
+        }
+        self.base_assets
+            .iter()
+            .map(|asset| parse_asset(asset).expect("base assets checked on load"))
+            .collect()
+    }
+
+    /// Pools to track. Decimals and fees are placeholders until `sync_state` reads them.
+    pub fn pools(&self) -> Vec<Pool> {
+        if self.pools.is_empty() {
+            return get_pools();
+        }
+        self.pools
+            .iter()
+            .map(|pool| Pool {
+                pool_name: format!("{:.10}/{:.10}", pool.asset_0, pool.asset_1),
+                from: parse_asset(&pool.asset_0).expect("pools checked on load"),
+                to: parse_asset(&pool.asset_1).expect("pools checked on load"),
+                is_stable: pool.is_stable,
+                reserve_0: U256::zero(),
+                reserve_1: U256::zero(),
+                decimals_0: 9,
+                decimals_1: 9,
+                fees: default_fees(),
+            })
+            .collect()
+    }
+}
+
+/// Reads the config from `path`, else `TRITON_CONFIG`, else `triton.toml` if present, falling
+/// back to mainnet defaults. `TRITON_*` variables, including ones from `.env`, override the file.
+pub fn load_config(path: Option<&str>) -> Result<Config, ConfigError> {
+    dotenvy::dotenv_override().ok();
+    let path = path
+        .map(str::to_string)
+        .or_else(|| env::var("TRITON_CONFIG").ok())
+        .or_else(|| {
+            Path::new(DEFAULT_CONFIG_PATH)
+                .exists()
+                .then(|| DEFAULT_CONFIG_PATH.to_string())
+        });
+
+    let mut config = match path {
+        Some(path) => {
+            let contents = fs::read_to_string(&path)
+                .map_err(|e| ConfigError::Read(format!("{}: {}", path, e)))?;
+            toml::from_str(&contents).map_err(|e| ConfigError::Parse(format!("{}: {}", path, e)))?
+        }
+        None => Config::default(),
+    };
+    apply_env_overrides(&mut config)?;
+    validate(&config)?;
+    Ok(config)
+}
+
+fn apply_env_overrides(config: &mut Config) -> Result<(), ConfigError> {
+    env_override("TRITON_NODE_URL", &mut config.node_url)?;
+    env_override("TRITON_CHAIN_ID", &mut config.chain_id)?;
+    env_override("TRITON_CONTRACT_ID", &mut config.contract_id)?;
+    env_override("TRITON_SCRIPT_SHA256", &mut config.script_sha256)?;
+    env_override("TRITON_MAX_HOPS", &mut config.max_hops)?;
+    env_override("TRITON_NUMERAIRE", &mut config.numeraire)?;
+    env_override("TRITON_GAS_ASSET", &mut config.gas_asset)?;
+    env_override("TRITON_MIN_NET_PROFIT", &mut config.min_net_profit)?;
+    env_override("TRITON_DRY_RUN", &mut config.dry_run)?;
+    // Set but empty lifts the limit
+    if let Ok(value) = env::var("TRITON_MAX_INPUT_VALUE") {
+        config.max_input_value =
+            if value.is_empty() {
+                None
+            } else {
+                Some(value.parse().map_err(|_| {
+                    ConfigError::Invalid(format!("TRITON_MAX_INPUT_VALUE={}", value))
+                })?)
+            };
+    }
+    // Set but empty uses the embedded script
+    if let Ok(value) = env::var("TRITON_SCRIPT_PATH") {
+        config.script_path = (!value.is_empty()).then_some(value);
+    }
+    // Comma separated asset ids
+    if let Ok(value) = env::var("TRITON_BASE_ASSETS") {
+        config.base_assets = value
+            .split(',')
+            .map(|asset| asset.trim().to_string())
+            .filter(|asset| !asset.is_empty())
+            .collect();
+    }
+    Ok(())
+}
+
+fn env_override<T: FromStr>(name: &str, target: &mut T) -> Result<(), ConfigError> {
+    let Ok(value) = env::var(name) else {
+        return Ok(());
+    };
+    if value.is_empty() {
+        return Ok(());
+    }
+    *target = value
+        .parse()
+        .map_err(|_| ConfigError::Invalid(format!("{}={}", name, value)))?;
+    Ok(())
+}
+
+fn validate(config: &Config) -> Result<(), ConfigError> {
+    ContractId::from_str(&config.contract_id)
+        .map_err(|_| ConfigError::Invalid(format!("contract_id {}", config.contract_id)))?;
+    let assets = config
+        .base_assets
+        .iter()
+        .chain([&config.numeraire, &config.gas_asset])
+        .chain(
+            config
+                .pools
+                .iter()
+                .flat_map(|pool| [&pool.asset_0, &pool.asset_1]),
+        );
+    for asset in assets {
+        parse_asset(asset).ok_or_else(|| ConfigError::Invalid(format!("asset id {}", asset)))?;
+    }
+    if config.max_hops < 2 {
+        return Err(ConfigError::Invalid(format!(
+            "max_hops {}, cycles need at least 2 pools",
+            config.max_hops
+        )));
+    }
+    Ok(())
+}
+
+fn parse_asset(asset: &str) -> Option<AssetId> {
+    AssetId::from_str(asset).ok()
+}
@@ -2,3 +2,8 @@ pub const CONTRACT_ID: &str = "0x2e40f2b244b98ed6b8204b3de0156c6961f98525c8162f8
 pub const MIRA_SWAP_EVENT_ID: u64 = 7938487056892321597;
 pub const MIRA_MINT_EVENT_ID: u64 = 12547144180067087194;
 pub const MIRA_BURN_EVENT_ID: u64 = 3903049024573527731;
+pub const MIRA_CREATE_POOL_EVENT_ID: u64 = 12224862438738316526;
+pub const BASIS_POINTS_DENOMINATOR: u64 = 10_000;
+// sha256 of `src/contracts/swap_exact_input_script.bin` before configurables are set
+pub const SWAP_SCRIPT_SHA256: &str =
+    "9be1fc480f4f1c6153b1f952a12b9147bd6368b8c95ebd7dac64f5f043bb4337";
@@ -0,0 +1,229 @@
+use std::{collections::HashSet, time::Instant};
+
+use ethers::{types::U256, utils::format_units};
+use fuels::accounts::{wallet::WalletUnlocked, ViewOnlyAccount};
+use futures::future::LocalBoxFuture;
+use log::{error, info};
+
+use crate::{
+    bundle::{self, ExecutionOutcome, ExecutionStatus},
+    calc::{find_negative_cycles, OpportunityCache},
+    recon::{backfill_mira_pools_pangea, replay_state_pangea, sync_state, SyncError},
+    reconcile::reconcile_fill,
+    strategy::Strategy,
+    triton::Triton,
+    types::{Action, Config, Event},
+    valuation::{gas_shape, Valuation},
+};
+
+/// Cycles through Mira pools that start and end at the same asset. Reprices the cycles
+/// through whichever pool an event touched and asks to execute the best one net of gas.
+pub struct CyclicArb {
+    pub triton: Triton,
+    wallet: WalletUnlocked,
+    config: Config,
+    opportunities: OpportunityCache,
+    valuation: Valuation,
+    // Rebuild every pool from Mira's full log history on sync and verify it against the contract
+    replay: bool,
+}
+
+impl CyclicArb {
+    pub fn new(config: Config, wallet: WalletUnlocked, replay: bool) -> CyclicArb {
+        CyclicArb {
+            triton: Triton::new(&config),
+            wallet,
+            opportunities: OpportunityCache::default(),
+            // Profits are compared in the configured numeraire after paying for gas
+            valuation: Valuation::new(&config),
+            config,
+            replay,
+        }
+    }
+
+    // Snapshots reserves and adds pools created on Mira since `get_pools` was written, up to
+    // the snapshot height. Pools found that way have no reserves yet, so snapshot again until
+    // a pass turns up nothing new.
+    async fn sync_with_new_pools(&mut self) -> Result<u64, SyncError> {
+        let mut from_block = 0;
+        loop {
+            let snapshot_block =
+                sync_state(&mut self.triton, self.wallet.clone(), &self.config).await?;
+            let (tx, rx) = crossbeam_channel::unbounded::<Event>();
+            if backfill_mira_pools_pangea(tx, &self.config, from_block, snapshot_block)
+                .await
+                .is_err()
+            {
+                error!("Error in backfill_mira_pools");
+                return Ok(snapshot_block);
+            }
+            let mut events = rx.try_iter().collect::<Vec<Event>>();
+            events.sort_by_key(Event::position);
+            let mut added = false;
+            for event in events {
+                // `process_event` skips anything at or below the snapshot, so add pools directly
+                if let Event::MiraCreatePool(event) = event {
+                    added |= self.triton.handle_create_pool(&event).is_some();
+                }
+            }
+            if !added {
+                return Ok(snapshot_block);
+            }
+            from_block = snapshot_block + 1;
+        }
+    }
+
+    // Snapshots every pool again after an event didn't fit the reserves we had. Events the
+    // feed still holds at or below the new snapshot are skipped as already reflected.
+    async fn resync(&mut self) {
+        info!("Resyncing pools {:?}", self.triton.stale_pools);
+        // Pools stay flagged on failure, so the next event tries again
+        if let Err(e) = sync_state(&mut self.triton, self.wallet.clone(), &self.config).await {
+            error!("Failed to resync pools: {:?}", e);
+            return;
+        }
+        self.opportunities.refresh_all(&self.triton);
+    }
+
+    // Caps cycle inputs at what the wallet actually holds of each asset
+    async fn load_balances(&mut self) {
+        for asset in self.triton.graph.tokens() {
+            match self.wallet.get_asset_balance(asset).await {
+                Ok(balance) => self.opportunities.set_balance(*asset, U256::from(balance)),
+                Err(e) => error!("Failed to read balance of {}: {:?}", asset, e),
+            }
+        }
+    }
+}
+
+impl Strategy for CyclicArb {
+    fn sync_state(&mut self) -> LocalBoxFuture<'_, Result<u64, SyncError>> {
+        Box::pin(async move {
+            println!("triton: {:?}", self.triton.cycles.len());
+            if self.replay {
+                match replay_state_pangea(&mut self.triton, self.wallet.clone(), &self.config).await
+                {
+                    Ok(mismatches) if mismatches.is_empty() => {
+                        info!("Replayed reserves match contract")
+                    }
+                    Ok(mismatches) => error!("Replayed reserves differ for pools {:?}", mismatches),
+                    Err(_) => error!("Error in replay_state"),
+                }
+            }
+
+            // Snapshot reserves at a known height so the stream picks up exactly where it left off
+            let snapshot_block = match self.triton.synced_block {
+                Some(block) => block,
+                None => match self.sync_with_new_pools().await {
+                    Ok(block) => block,
+                    Err(e) => return Err(e),
+                },
+            };
+
+            // Price everything once, afterwards only cycles through the pool an event touched
+            self.load_balances().await;
+            self.opportunities.refresh_all(&self.triton);
+            Ok(snapshot_block)
+        })
+    }
+
+    fn process_event(&mut self, event: Event) -> LocalBoxFuture<'_, Vec<Action>> {
+        Box::pin(async move {
+            let changed = self.triton.process_event(event);
+            // Reserves that drifted from the chain are read again before anything is priced
+            if !self.triton.stale_pools.is_empty() {
+                self.resync().await;
+                return Vec::new();
+            }
+            let Some(pool_index) = changed else {
+                return Vec::new();
+            };
+            let now = Instant::now();
+            let repriced = self.opportunities.refresh_pool(&self.triton, pool_index);
+            // Loops longer than `max_hops` only show up here
+            for cycle in find_negative_cycles(&self.triton) {
+                if let Some(cycle_index) = self.triton.track_cycle(cycle) {
+                    info!("Negative cycle search found new cycle {}", cycle_index);
+                    self.opportunities.refresh_cycle(&self.triton, cycle_index);
+                }
+            }
+            println!(
+                "triton: {:?} of {:?} cycles repriced",
+                repriced,
+                self.triton.cycles.len()
+            );
+            let cycles = self.opportunities.ranked();
+            let elapsed = now.elapsed().as_millis();
+            println!("Cycle finding took {}ms", elapsed);
+            if cycles.is_empty() {
+                return Vec::new();
+            }
+
+            if let Err(e) = self
+                .valuation
+                .refresh_gas_price(self.wallet.provider().unwrap())
+                .await
+            {
+                error!("Failed to read gas price: {:?}", e);
+                return Vec::new();
+            }
+            // Gas barely depends on amounts, so one simulation per run of stable and volatile
+            // hops is enough until it expires. Every profitable cycle needs one before it can be
+            // compared in the numeraire, but a shape that fails is only tried once per event.
+            let mut simulated = HashSet::new();
+            for cycle in &cycles {
+                if !self.valuation.has_gas_estimate(cycle) && simulated.insert(gas_shape(cycle)) {
+                    match bundle::simulate_gas(&self.wallet, &self.config, cycle).await {
+                        Ok(gas_used) => self.valuation.record_gas(cycle, gas_used),
+                        Err(e) => error!(
+                            "Failed to simulate {}-hop cycle for gas: {:?}",
+                            cycle.cycle_ids.len(),
+                            e
+                        ),
+                    }
+                }
+            }
+            let Some(best) = self.valuation.rank(&self.triton, cycles).into_iter().next() else {
+                return Vec::new();
+            };
+
+            let decimals = self.triton.asset_decimals(best.cycle.token_in).unwrap_or(9);
+            vec![
+                Action::Log(format!(
+                    "Most profitable cycle: {:?} profit as u64: {:?} net of gas in numeraire: {}",
+                    best.cycle,
+                    format_units(best.cycle.profit, decimals as u32),
+                    best.net_profit
+                )),
+                Action::ExecuteCycle(best.cycle),
+            ]
+        })
+    }
+
+    fn on_execution<'a>(
+        &'a mut self,
+        action: &'a Action,
+        outcome: &'a ExecutionOutcome,
+    ) -> LocalBoxFuture<'a, ()> {
+        Box::pin(async move {
+            let Action::ExecuteCycle(cycle) = action else {
+                return;
+            };
+            // Apply our own swaps now rather than trading on stale reserves until the feed
+            if let Some(fill) = reconcile_fill(
+                &mut self.triton,
+                cycle,
+                outcome,
+                self.wallet.address().into(),
+                &self.config,
+            ) {
+                for pool_index in &fill.updated_pools {
+                    self.opportunities.refresh_pool(&self.triton, *pool_index);
+                }
+            }
+            if let ExecutionStatus::Success = outcome.status {
+                self.load_balances().await;
+            }
+        })
+    }
+}
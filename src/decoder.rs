@@ -0,0 +1,334 @@
+use crate::{
+    constants::{
+        MIRA_BURN_EVENT_ID, MIRA_CREATE_POOL_EVENT_ID, MIRA_MINT_EVENT_ID, MIRA_SWAP_EVENT_ID,
+    },
+    types::{
+        Asset, BurnEvent, BurnEventWithTx, CreatePoolEvent, CreatePoolEventWithTx, Event,
+        EventPosition, MintEvent, MintEventWithTx, PangeaLogData, SwapEvent, SwapEventWithTx,
+    },
+};
+use ethers::utils::hex;
+use fuels::core::{
+    codec::ABIDecoder,
+    traits::{Parameterize, Tokenizable},
+};
+use log::{debug, error, warn};
+
+/// A Mira log decoded from its raw `LogData` receipt, before any transaction context is attached
+#[derive(Debug, PartialEq)]
+pub enum MiraLog {
+    Swap(SwapEvent),
+    Mint(MintEvent),
+    Burn(BurnEvent),
+    CreatePool(CreatePoolEvent),
+}
+
+impl MiraLog {
+    pub fn with_tx(self, tx_id: String, position: EventPosition) -> Event {
+        let EventPosition {
+            block_number,
+            tx_index,
+            log_index,
+        } = position;
+        match self {
+            MiraLog::Swap(event) => Event::MiraSwap(SwapEventWithTx {
+                tx_id,
+                block_number,
+                tx_index,
+                log_index,
+                pool_id: event.pool_id,
+                recipient: event.recipient,
+                asset_0_in: event.asset_0_in,
+                asset_1_in: event.asset_1_in,
+                asset_0_out: event.asset_0_out,
+                asset_1_out: event.asset_1_out,
+            }),
+            MiraLog::Mint(event) => Event::MiraMint(MintEventWithTx {
+                tx_id,
+                block_number,
+                tx_index,
+                log_index,
+                pool_id: event.pool_id,
+                recipient: event.recipient,
+                liquidity: event.liquidity,
+                asset_0_in: event.asset_0_in,
+                asset_1_in: event.asset_1_in,
+            }),
+            MiraLog::Burn(event) => Event::MiraBurn(BurnEventWithTx {
+                tx_id,
+                block_number,
+                tx_index,
+                log_index,
+                pool_id: event.pool_id,
+                recipient: event.recipient,
+                liquidity: event.liquidity,
+                asset_0_out: event.asset_0_out,
+                asset_1_out: event.asset_1_out,
+            }),
+            MiraLog::CreatePool(event) => Event::MiraCreatePool(CreatePoolEventWithTx {
+                tx_id,
+                block_number,
+                tx_index,
+                log_index,
+                pool_id: event.pool_id,
+                decimals_0: event.decimals_0,
+                decimals_1: event.decimals_1,
+            }),
+        }
+    }
+}
+
+fn decode<T: Parameterize + Tokenizable>(data: &[u8]) -> Option<T> {
+    let token = ABIDecoder::default()
+        .decode(&T::param_type(), data)
+        .map_err(|e| error!("Failed to decode Mira log: {:?}", e))
+        .ok()?;
+    T::from_token(token)
+        .map_err(|e| error!("Failed to decode Mira log: {:?}", e))
+        .ok()
+}
+
+/// ABI-decodes the body of a Mira `LogData` receipt, dispatching on its `rb` log id
+pub fn decode_log_data(rb: u64, data: &[u8]) -> Option<MiraLog> {
+    match rb {
+        MIRA_SWAP_EVENT_ID => {
+            let event: mira_v1::interface::SwapEvent = decode(data)?;
+            Some(MiraLog::Swap(SwapEvent {
+                pool_id: (
+                    event.pool_id.0.into(),
+                    event.pool_id.1.into(),
+                    event.pool_id.2,
+                ),
+                recipient: event.recipient,
+                asset_0_in: event.asset_0_in,
+                asset_1_in: event.asset_1_in,
+                asset_0_out: event.asset_0_out,
+                asset_1_out: event.asset_1_out,
+            }))
+        }
+        MIRA_MINT_EVENT_ID => {
+            let event: mira_v1::interface::MintEvent = decode(data)?;
+            Some(MiraLog::Mint(MintEvent {
+                pool_id: (
+                    event.pool_id.0.into(),
+                    event.pool_id.1.into(),
+                    event.pool_id.2,
+                ),
+                recipient: event.recipient,
+                liquidity: Asset {
+                    id: event.liquidity.id.into(),
+                    amount: event.liquidity.amount,
+                },
+                asset_0_in: event.asset_0_in,
+                asset_1_in: event.asset_1_in,
+            }))
+        }
+        MIRA_BURN_EVENT_ID => {
+            let event: mira_v1::interface::BurnEvent = decode(data)?;
+            Some(MiraLog::Burn(BurnEvent {
+                pool_id: (
+                    event.pool_id.0.into(),
+                    event.pool_id.1.into(),
+                    event.pool_id.2,
+                ),
+                recipient: event.recipient,
+                liquidity: Asset {
+                    id: event.liquidity.id.into(),
+                    amount: event.liquidity.amount,
+                },
+                asset_0_out: event.asset_0_out,
+                asset_1_out: event.asset_1_out,
+            }))
+        }
+        MIRA_CREATE_POOL_EVENT_ID => {
+            let event: mira_v1::interface::CreatePoolEvent = decode(data)?;
+            Some(MiraLog::CreatePool(CreatePoolEvent {
+                pool_id: (
+                    event.pool_id.0.into(),
+                    event.pool_id.1.into(),
+                    event.pool_id.2,
+                ),
+                decimals_0: event.decimals_0,
+                decimals_1: event.decimals_1,
+            }))
+        }
+        _ => None,
+    }
+}
+
+/// Same as `decode_log_data` for sources that hand out the receipt's `rb` and `data` as hex
+pub fn decode_log_data_hex(rb: &str, data: &str) -> Option<MiraLog> {
+    let rb = u64::from_str_radix(rb.trim_start_matches("0x"), 16).ok()?;
+    let data = hex::decode(data.trim_start_matches("0x"))
+        .map_err(|e| error!("Invalid log data hex: {:?}", e))
+        .ok()?;
+    decode_log_data(rb, &data)
+}
+
+/// Decodes Pangea's pre-decoded JSON for a log, dispatching on its `rb` log id
+pub fn decode_pangea_json(rb: u64, decoded: &str) -> Option<MiraLog> {
+    let log = match rb {
+        MIRA_SWAP_EVENT_ID => serde_json::from_str(decoded).map(MiraLog::Swap),
+        MIRA_MINT_EVENT_ID => serde_json::from_str(decoded).map(MiraLog::Mint),
+        MIRA_BURN_EVENT_ID => serde_json::from_str(decoded).map(MiraLog::Burn),
+        MIRA_CREATE_POOL_EVENT_ID => serde_json::from_str(decoded).map(MiraLog::CreatePool),
+        _ => return None,
+    };
+    log.map_err(|e| error!("Failed to decode Pangea log: {:?}", e))
+        .ok()
+}
+
+/// Checks Pangea's decoding of a log against our own decoding of its raw receipt data
+pub fn verify_pangea_log(data: &PangeaLogData) -> bool {
+    let rb = match u64::from_str_radix(data.rb.trim_start_matches("0x"), 16) {
+        Ok(rb) => rb,
+        Err(_) => return false,
+    };
+    let ours = decode_log_data_hex(&data.rb, &data.data);
+    let theirs = decode_pangea_json(rb, &data.decoded);
+    if ours != theirs {
+        warn!(
+            "Pangea decoding of {} disagrees with receipt data: ours={:?} pangea={:?}",
+            data.transaction_hash, ours, theirs
+        );
+        return false;
+    }
+    debug!("Pangea decoding of {} verified", data.transaction_hash);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_log_data_hex, decode_pangea_json, MiraLog};
+    use crate::constants::{
+        MIRA_BURN_EVENT_ID, MIRA_CREATE_POOL_EVENT_ID, MIRA_MINT_EVENT_ID, MIRA_SWAP_EVENT_ID,
+    };
+
+    // `LogData` bodies as Mira emits them: u64s big endian, bools and u8s one byte, enums a
+    // u64 variant index before the payload
+    const SWAP_DATA: &str = concat!(
+        // pool_id: asset 0, asset 1, is_stable
+        "1111111111111111111111111111111111111111111111111111111111111111",
+        "2222222222222222222222222222222222222222222222222222222222222222",
+        "00",
+        // recipient: Identity::Address
+        "0000000000000000",
+        "3333333333333333333333333333333333333333333333333333333333333333",
+        // asset_0_in, asset_1_in, asset_0_out, asset_1_out
+        "00000000000003e8",
+        "0000000000000000",
+        "0000000000000000",
+        "00000000000007c6",
+    );
+
+    const MINT_DATA: &str = concat!(
+        // pool_id
+        "1111111111111111111111111111111111111111111111111111111111111111",
+        "2222222222222222222222222222222222222222222222222222222222222222",
+        "01",
+        // recipient: Identity::ContractId
+        "0000000000000001",
+        "3333333333333333333333333333333333333333333333333333333333333333",
+        // liquidity: id, amount
+        "4444444444444444444444444444444444444444444444444444444444444444",
+        "0000000000001388",
+        // asset_0_in, asset_1_in
+        "00000000000007d0",
+        "0000000000000bb8",
+    );
+
+    const BURN_DATA: &str = concat!(
+        // pool_id
+        "1111111111111111111111111111111111111111111111111111111111111111",
+        "2222222222222222222222222222222222222222222222222222222222222222",
+        "00",
+        // recipient: Identity::Address
+        "0000000000000000",
+        "3333333333333333333333333333333333333333333333333333333333333333",
+        // liquidity: id, amount
+        "4444444444444444444444444444444444444444444444444444444444444444",
+        "0000000000001388",
+        // asset_0_out, asset_1_out
+        "00000000000007d0",
+        "0000000000000bb8",
+    );
+
+    const CREATE_POOL_DATA: &str = concat!(
+        // pool_id
+        "1111111111111111111111111111111111111111111111111111111111111111",
+        "2222222222222222222222222222222222222222222222222222222222222222",
+        "00",
+        // decimals_0, decimals_1
+        "09",
+        "06",
+    );
+    // Pangea's decoding of the same logs
+    const SWAP_JSON: &str = r#"{"asset_0_in":1000,"asset_0_out":0,"asset_1_in":0,"asset_1_out":1990,"pool_id":[{"bits":"0x1111111111111111111111111111111111111111111111111111111111111111"},{"bits":"0x2222222222222222222222222222222222222222222222222222222222222222"},false],"recipient":{"Address":{"bits":"0x3333333333333333333333333333333333333333333333333333333333333333"}}}"#;
+    const MINT_JSON: &str = r#"{"asset_0_in":2000,"asset_1_in":3000,"liquidity":{"id":{"bits":"0x4444444444444444444444444444444444444444444444444444444444444444"},"amount":5000},"pool_id":[{"bits":"0x1111111111111111111111111111111111111111111111111111111111111111"},{"bits":"0x2222222222222222222222222222222222222222222222222222222222222222"},true],"recipient":{"ContractId":{"bits":"0x3333333333333333333333333333333333333333333333333333333333333333"}}}"#;
+    const BURN_JSON: &str = r#"{"asset_0_out":2000,"asset_1_out":3000,"liquidity":{"id":{"bits":"0x4444444444444444444444444444444444444444444444444444444444444444"},"amount":5000},"pool_id":[{"bits":"0x1111111111111111111111111111111111111111111111111111111111111111"},{"bits":"0x2222222222222222222222222222222222222222222222222222222222222222"},false],"recipient":{"Address":{"bits":"0x3333333333333333333333333333333333333333333333333333333333333333"}}}"#;
+    const CREATE_POOL_JSON: &str = r#"{"pool_id":[{"bits":"0x1111111111111111111111111111111111111111111111111111111111111111"},{"bits":"0x2222222222222222222222222222222222222222222222222222222222222222"},false],"decimals_0":9,"decimals_1":6}"#;
+
+    // Decodes `data` from hex and `json` as Pangea, checking both give the same log
+    fn decode_both(rb: u64, data: &str, json: &str) -> MiraLog {
+        let ours = decode_log_data_hex(&format!("{:#x}", rb), data).expect("receipt data");
+        let theirs = decode_pangea_json(rb, json).expect("pangea json");
+        assert_eq!(ours, theirs);
+        ours
+    }
+
+    #[test]
+    fn swap_matches_pangea() {
+        let MiraLog::Swap(swap) = decode_both(MIRA_SWAP_EVENT_ID, SWAP_DATA, SWAP_JSON) else {
+            panic!("expected a swap");
+        };
+        assert_eq!(swap.asset_0_in, 1_000);
+        assert_eq!(swap.asset_1_out, 1_990);
+        assert!(!swap.pool_id.2);
+    }
+
+    #[test]
+    fn mint_matches_pangea() {
+        let MiraLog::Mint(mint) = decode_both(MIRA_MINT_EVENT_ID, MINT_DATA, MINT_JSON) else {
+            panic!("expected a mint");
+        };
+        assert_eq!(mint.liquidity.amount, 5_000);
+        assert_eq!((mint.asset_0_in, mint.asset_1_in), (2_000, 3_000));
+        assert!(mint.pool_id.2);
+    }
+
+    #[test]
+    fn burn_matches_pangea() {
+        let MiraLog::Burn(burn) = decode_both(MIRA_BURN_EVENT_ID, BURN_DATA, BURN_JSON) else {
+            panic!("expected a burn");
+        };
+        assert_eq!(burn.liquidity.amount, 5_000);
+        assert_eq!((burn.asset_0_out, burn.asset_1_out), (2_000, 3_000));
+    }
+
+    #[test]
+    fn create_pool_matches_pangea() {
+        let MiraLog::CreatePool(create_pool) = decode_both(
+            MIRA_CREATE_POOL_EVENT_ID,
+            CREATE_POOL_DATA,
+            CREATE_POOL_JSON,
+        ) else {
+            panic!("expected a pool creation");
+        };
+        assert_eq!((create_pool.decimals_0, create_pool.decimals_1), (9, 6));
+    }
+
+    #[test]
+    fn unknown_log_id_is_ignored() {
+        assert_eq!(decode_log_data_hex("0x1", SWAP_DATA), None);
+        assert_eq!(decode_pangea_json(1, SWAP_JSON), None);
+    }
+
+    #[test]
+    fn bad_data_is_rejected() {
+        let rb = format!("{:#x}", MIRA_SWAP_EVENT_ID);
+        assert_eq!(decode_log_data_hex(&rb, "0xnot-hex"), None);
+        assert_eq!(decode_log_data_hex("0xnot-hex", SWAP_DATA), None);
+        // Valid hex cut off partway through the log
+        assert_eq!(decode_log_data_hex(&rb, &SWAP_DATA[..100]), None);
+    }
+}
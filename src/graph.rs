@@ -0,0 +1,400 @@
+use std::collections::{HashMap, HashSet};
+
+use fuels::types::AssetId;
+
+use crate::triton::{Cycle, IndexedPair};
+
+// Smallest improvement that counts as a relaxation, keeps float noise from looking like a loop
+const NEGATIVE_CYCLE_EPSILON: f64 = 1e-12;
+
+/// A pool seen from one of its assets: swapping through `pair` yields `token_out`
+#[derive(Debug, Clone, Copy)]
+pub struct Edge {
+    pub pair: IndexedPair,
+    pub token_out: AssetId,
+}
+
+/// Token graph with assets as nodes and pools as edges, indexed by `AssetId`
+#[derive(Debug, Default)]
+pub struct TokenGraph {
+    adjacency: HashMap<AssetId, Vec<Edge>>,
+}
+
+impl TokenGraph {
+    pub fn new(pairs: &[IndexedPair]) -> TokenGraph {
+        let mut graph = TokenGraph::default();
+        for pair in pairs {
+            graph.add_pair(*pair);
+        }
+        graph
+    }
+
+    /// Adds a pool as an edge usable in either direction
+    pub fn add_pair(&mut self, pair: IndexedPair) {
+        let (asset_0, asset_1, _) = pair.pair;
+        self.adjacency.entry(asset_0).or_default().push(Edge {
+            pair,
+            token_out: asset_1,
+        });
+        self.adjacency.entry(asset_1).or_default().push(Edge {
+            pair,
+            token_out: asset_0,
+        });
+    }
+
+    /// Every asset in at least one pool
+    pub fn tokens(&self) -> impl Iterator<Item = &AssetId> {
+        self.adjacency.keys()
+    }
+
+    /// Pools that can be entered holding `token`
+    pub fn edges(&self, token: &AssetId) -> &[Edge] {
+        self.adjacency
+            .get(token)
+            .map(|edges| edges.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Enumerates simple cycles of two to `max_hops` pools that start and end at one of
+    /// `base_assets`. Each loop is returned once, starting from the first base asset it
+    /// touches, so rotations and reversals of it are not repeated.
+    pub fn find_cycles(&self, base_assets: &[AssetId], max_hops: usize) -> Vec<Cycle> {
+        let mut cycles = Vec::new();
+        let mut seen_loops = HashSet::new();
+
+        for base_asset in base_assets {
+            let mut search = CycleSearch {
+                graph: self,
+                start: *base_asset,
+                max_hops,
+                path: Vec::new(),
+                tokens: vec![*base_asset],
+                seen_loops: &mut seen_loops,
+                cycles: &mut cycles,
+            };
+            search.extend(*base_asset);
+        }
+
+        cycles
+    }
+
+    /// Cycles of up to `max_hops` pools that route through `pair`, for a pool just added to
+    /// the graph: paths from its second asset back to its first, closed by the pool itself.
+    /// Loops that touch none of `base_assets` are dropped and the rest start at the first base
+    /// asset they touch, like `find_cycles` would return them.
+    pub fn find_cycles_through(
+        &self,
+        pair: IndexedPair,
+        base_assets: &[AssetId],
+        max_hops: usize,
+    ) -> Vec<Cycle> {
+        let (asset_0, asset_1, _) = pair.pair;
+        let mut cycles = Vec::new();
+        let mut seen_loops = HashSet::new();
+        let mut search = CycleSearch {
+            graph: self,
+            start: asset_0,
+            max_hops,
+            path: vec![pair],
+            tokens: vec![asset_0, asset_1],
+            seen_loops: &mut seen_loops,
+            cycles: &mut cycles,
+        };
+        search.extend(asset_1);
+
+        cycles
+            .into_iter()
+            .filter_map(|cycle| start_at_base_asset(cycle, base_assets))
+            .collect()
+    }
+
+    /// Finds loops whose edge weights sum below zero with Bellman-Ford, relaxing from every
+    /// asset at once. `weight` is the cost of entering `edge` holding the given token, or None
+    /// to leave the edge out. Unlike `find_cycles` loops may be any length and start anywhere.
+    pub fn find_negative_cycles(
+        &self,
+        weight: impl Fn(&AssetId, &Edge) -> Option<f64>,
+    ) -> Vec<Cycle> {
+        let weight = &weight;
+        let weighted_edges = self
+            .adjacency
+            .iter()
+            .flat_map(|(token_in, edges)| {
+                edges
+                    .iter()
+                    .filter_map(move |edge| Some((*token_in, *edge, weight(token_in, edge)?)))
+            })
+            .collect::<Vec<(AssetId, Edge, f64)>>();
+
+        let node_count = self.adjacency.len();
+        let mut distance = self
+            .adjacency
+            .keys()
+            .map(|token| (*token, 0.0))
+            .collect::<HashMap<AssetId, f64>>();
+        let mut predecessor: HashMap<AssetId, (AssetId, IndexedPair)> = HashMap::new();
+
+        // Anything still relaxing after one pass per node sits on or behind a negative loop
+        let mut relaxed = Vec::new();
+        for _ in 0..node_count {
+            relaxed.clear();
+            for (token_in, edge, cost) in &weighted_edges {
+                let candidate = distance[token_in] + cost;
+                if candidate < distance[&edge.token_out] - NEGATIVE_CYCLE_EPSILON {
+                    distance.insert(edge.token_out, candidate);
+                    predecessor.insert(edge.token_out, (*token_in, edge.pair));
+                    relaxed.push(edge.token_out);
+                }
+            }
+            if relaxed.is_empty() {
+                return Vec::new();
+            }
+        }
+
+        let mut cycles = Vec::new();
+        let mut seen_loops = HashSet::new();
+        for token in relaxed {
+            if let Some(cycle) = negative_cycle_through(&predecessor, token, node_count) {
+                if cycle.cycle.len() > 1 && seen_loops.insert(cycle.loop_key()) {
+                    cycles.push(cycle);
+                }
+            }
+        }
+        cycles
+    }
+}
+
+// Follows predecessors back from `token` onto the loop it hangs off, then reads the loop out
+fn negative_cycle_through(
+    predecessor: &HashMap<AssetId, (AssetId, IndexedPair)>,
+    token: AssetId,
+    node_count: usize,
+) -> Option<Cycle> {
+    // After one step per node the walk is guaranteed to be inside the loop
+    let mut start = token;
+    for _ in 0..node_count {
+        start = predecessor.get(&start)?.0;
+    }
+
+    let mut path = Vec::new();
+    let mut token = start;
+    loop {
+        let (previous, pair) = *predecessor.get(&token)?;
+        path.push(pair);
+        token = previous;
+        if token == start {
+            break;
+        }
+        if path.len() > node_count {
+            return None;
+        }
+    }
+    // Predecessors run backwards, so flip the path into swap order
+    path.reverse();
+    Some(Cycle { start, cycle: path })
+}
+
+/// The same loop entered at the first of `base_assets` it touches, None if it touches none
+pub fn start_at_base_asset(cycle: Cycle, base_assets: &[AssetId]) -> Option<Cycle> {
+    let tokens = cycle_tokens(&cycle);
+    let base_asset = base_assets.iter().find(|asset| tokens.contains(asset))?;
+    Some(rotate_to(cycle, *base_asset))
+}
+
+// Token entering each pool of `cycle`, starting with `cycle.start`
+fn cycle_tokens(cycle: &Cycle) -> Vec<AssetId> {
+    let mut tokens = Vec::with_capacity(cycle.cycle.len());
+    let mut token = cycle.start;
+    for pair in &cycle.cycle {
+        tokens.push(token);
+        let (asset_0, asset_1, _) = pair.pair;
+        token = if token == asset_0 { asset_1 } else { asset_0 };
+    }
+    tokens
+}
+
+// The same loop in the same direction, entered at `start` instead
+fn rotate_to(cycle: Cycle, start: AssetId) -> Cycle {
+    let offset = cycle_tokens(&cycle)
+        .iter()
+        .position(|token| *token == start)
+        .unwrap_or(0);
+    let mut pairs = cycle.cycle;
+    pairs.rotate_left(offset);
+    Cycle {
+        start,
+        cycle: pairs,
+    }
+}
+
+// Depth-first search state for `TokenGraph::find_cycles`, rooted at one asset
+struct CycleSearch<'a> {
+    graph: &'a TokenGraph,
+    start: AssetId,
+    max_hops: usize,
+    path: Vec<IndexedPair>,
+    // Tokens visited along `path`, starting with `start`
+    tokens: Vec<AssetId>,
+    // Sorted pool indices of every loop found so far
+    seen_loops: &'a mut HashSet<Vec<usize>>,
+    cycles: &'a mut Vec<Cycle>,
+}
+
+impl CycleSearch<'_> {
+    fn extend(&mut self, token_in: AssetId) {
+        for edge in self.graph.edges(&token_in) {
+            // Skip pools already on the path
+            if self.path.iter().any(|used| used.index == edge.pair.index) {
+                continue;
+            }
+
+            if edge.token_out == self.start {
+                // Prevent single-pool cycles
+                if self.path.is_empty() {
+                    continue;
+                }
+                let mut loop_key = self
+                    .path
+                    .iter()
+                    .map(|pair| pair.index)
+                    .chain(std::iter::once(edge.pair.index))
+                    .collect::<Vec<usize>>();
+                loop_key.sort();
+                if self.seen_loops.insert(loop_key) {
+                    let mut cycle = self.path.clone();
+                    cycle.push(edge.pair);
+                    self.cycles.push(Cycle {
+                        start: self.start,
+                        cycle,
+                    });
+                }
+            } else if self.path.len() + 1 < self.max_hops && !self.tokens.contains(&edge.token_out)
+            {
+                self.path.push(edge.pair);
+                self.tokens.push(edge.token_out);
+                self.extend(edge.token_out);
+                self.tokens.pop();
+                self.path.pop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use ethers::types::U256;
+    use fuels::types::AssetId;
+
+    use super::{cycle_tokens, rotate_to, Edge, TokenGraph};
+    use crate::{
+        tokens::default_fees,
+        triton::{Cycle, IndexedPair, Triton},
+        types::Pool,
+    };
+
+    fn asset(index: u8) -> AssetId {
+        AssetId::new([index + 1; 32])
+    }
+
+    fn pair(index: usize, asset_0: u8, asset_1: u8) -> IndexedPair {
+        IndexedPair {
+            index,
+            pair: (asset(asset_0), asset(asset_1), false),
+        }
+    }
+
+    fn pool(asset_0: u8, asset_1: u8, is_stable: bool) -> Pool {
+        Pool {
+            pool_name: format!("{}/{}", asset_0, asset_1),
+            from: asset(asset_0),
+            to: asset(asset_1),
+            is_stable,
+            reserve_0: U256::from(1_000_000),
+            reserve_1: U256::from(1_000_000),
+            decimals_0: 9,
+            decimals_1: 9,
+            fees: default_fees(),
+        }
+    }
+
+    // -ln of `rates`, keyed by pool and quoted from each pool's first asset to its second
+    fn weight(rates: HashMap<usize, f64>) -> impl Fn(&AssetId, &Edge) -> Option<f64> {
+        move |token_in, edge| {
+            let rate = rates[&edge.pair.index];
+            let rate = if *token_in == edge.pair.pair.0 {
+                rate
+            } else {
+                1.0 / rate
+            };
+            Some(-rate.ln())
+        }
+    }
+
+    // Loop key and start of every cycle, which is what rotation and reversal must not change
+    fn loops(cycles: &[Cycle]) -> HashSet<(Vec<usize>, AssetId)> {
+        cycles
+            .iter()
+            .map(|cycle| (cycle.loop_key(), cycle.start))
+            .collect()
+    }
+
+    #[test]
+    fn mispriced_triangle_is_a_negative_cycle() {
+        let graph = TokenGraph::new(&[pair(0, 0, 1), pair(1, 1, 2), pair(2, 2, 0)]);
+        // 0 -> 1 -> 2 -> 0 returns 2.0 * 1.0 * 0.6 = 1.2 of what went in
+        let rates = HashMap::from([(0, 2.0), (1, 1.0), (2, 0.6)]);
+
+        let cycles = graph.find_negative_cycles(weight(rates));
+        assert_eq!(cycles.len(), 1);
+        let cycle = rotate_to(cycles[0].clone(), asset(0));
+        assert_eq!(cycle.loop_key(), vec![0, 1, 2]);
+        // Only the profitable direction is a negative loop
+        assert_eq!(cycle_tokens(&cycle), vec![asset(0), asset(1), asset(2)]);
+
+        // Priced consistently there is nothing to find
+        let balanced = HashMap::from([(0, 2.0), (1, 1.0), (2, 0.5)]);
+        assert!(graph.find_negative_cycles(weight(balanced)).is_empty());
+    }
+
+    #[test]
+    fn rotations_and_reversals_are_found_once() {
+        let graph = TokenGraph::new(&[pair(0, 0, 1), pair(1, 1, 2), pair(2, 2, 0)]);
+        // Every asset is a base asset, so the triangle is reachable from each in both directions
+        let cycles = graph.find_cycles(&[asset(0), asset(1), asset(2)], 3);
+        assert_eq!(cycles.len(), 1);
+        assert_eq!(cycles[0].start, asset(0));
+
+        let rotated = rotate_to(cycles[0].clone(), asset(1));
+        assert_eq!(rotated.start, asset(1));
+        assert_eq!(rotated.loop_key(), cycles[0].loop_key());
+        let mut tokens = cycle_tokens(&cycles[0]);
+        tokens.rotate_left(1);
+        assert_eq!(cycle_tokens(&rotated), tokens);
+    }
+
+    #[test]
+    fn cycles_through_added_pools_match_a_full_search() {
+        let base_assets = vec![asset(0), asset(1)];
+        let initial = vec![pool(0, 1, false), pool(1, 2, false), pool(2, 0, false)];
+        let added = vec![pool(0, 1, true), pool(2, 3, false), pool(3, 0, false)];
+
+        let mut incremental = Triton::new_with_pools(initial, base_assets.clone(), 3);
+        for pool in added {
+            incremental.add_pool(pool);
+        }
+        let all = vec![
+            pool(0, 1, false),
+            pool(1, 2, false),
+            pool(2, 0, false),
+            pool(0, 1, true),
+            pool(2, 3, false),
+            pool(3, 0, false),
+        ];
+        let full = Triton::new_with_pools(all, base_assets, 3);
+
+        assert_eq!(incremental.cycles.len(), full.cycles.len());
+        assert_eq!(loops(&incremental.cycles), loops(&full.cycles));
+    }
+}
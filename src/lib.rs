@@ -1,8 +1,17 @@
 pub mod bundle;
 pub mod calc;
+pub mod config;
 pub mod constants;
+pub mod cyclic_arb;
+pub mod decoder;
+pub mod graph;
 pub mod recon;
+pub mod reconcile;
+pub mod script_math;
+pub mod signer;
+pub mod sources;
 pub mod strategy;
 pub mod tokens;
 pub mod triton;
 pub mod types;
+pub mod valuation;
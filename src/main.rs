@@ -1,53 +1,63 @@
-use ethers::utils::format_units;
 use fuels::accounts::provider::Provider;
-use fuels::accounts::wallet::WalletUnlocked;
+use fuels::accounts::ViewOnlyAccount;
 use log::{error, info};
+use std::time::Duration;
 use triton::bundle;
-use triton::calc::find_optimal_cycles;
-use triton::recon::{stream_mira_events_pangea, sync_state};
-use triton::types::Event;
+use triton::config::load_config;
+use triton::cyclic_arb::CyclicArb;
+use triton::signer::load_wallet;
+use triton::sources::{FuelNodeSource, PangeaSource};
+use triton::strategy::{CycleExecutor, Engine, LogExecutor};
 
 #[tokio::main]
 async fn main() {
     info!("Starting Triton Arbitrage bot");
-    let (tx, rx) = crossbeam_channel::unbounded::<Event>();
 
-    let mut triton = triton::triton::Triton::new();
-    println!("triton: {:?}", triton.cycles.len());
-    // Spawn a task to stream Mira events
-    let wallet = WalletUnlocked::new_from_private_key(
-        "0xf2331315499db8ff7868636f12863d514fd232dbbff1510043e78bc248c79e84"
-            .parse()
-            .unwrap(),
-        Some(Provider::connect("mainnet.fuel.network").await.unwrap()),
-    );
-
-    sync_state(&mut triton, wallet.clone()).await;
-    let event_tx = tx.clone();
-    info!("Starting Mira event stream");
-    tokio::spawn(async move {
-        if let Err(_) = stream_mira_events_pangea(event_tx).await {
-            error!("Error in stream_mira_events");
+    // `--config <path>`, otherwise `TRITON_CONFIG` or `triton.toml`
+    let config_path = std::env::args().skip_while(|arg| arg != "--config").nth(1);
+    let mut config = match load_config(config_path.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Failed to load config: {:?}", e);
+            return;
+        }
+    };
+    // Swaps load this checked private copy from here on, it is removed when main returns
+    let script = match bundle::prepare_script(&config) {
+        Ok(script) => script,
+        Err(e) => {
+            error!("Swap script check failed: {:?}", e);
+            return;
         }
-    });
+    };
+    config.script_path = Some(script.path.to_string_lossy().into_owned());
+    // Simulate cycles without ever sending them
+    config.dry_run |= std::env::args().any(|arg| arg == "--dry-run");
 
-    loop {
-        let event = rx.recv().unwrap();
-        triton.process_event(event);
-        let now = std::time::Instant::now();
-        println!("triton: {:?}", triton.cycles.len());
-        let cycles = find_optimal_cycles(&mut triton);
-        let elapsed = now.elapsed().as_millis();
-        println!("Cycle finding took {}ms", elapsed);
-        if !cycles.is_empty() {
-            println!(
-                "Most profitable cycle: {:?} profit as u64: {:?}",
-                cycles[0],
-                format_units(cycles[0].profit, 6)
-            );
-            bundle::send_multi_hop(&wallet, cycles[0].clone()).await;
-            let elapsed = now.elapsed().as_millis();
-            println!("Cycle execution took {}ms", elapsed);
+    let provider = Provider::connect(&config.node_url).await.unwrap();
+    let wallet = match load_wallet(provider, config.chain_id) {
+        Ok((wallet, _)) => wallet,
+        Err(e) => {
+            error!("Failed to load wallet: {:?}", e);
+            return;
         }
+    };
+
+    let mut engine = Engine::default();
+    if std::env::args().any(|arg| arg == "--fuel-node") {
+        engine.add_collector(FuelNodeSource {
+            provider: wallet.provider().unwrap().clone(),
+            contract_id: config.contract_id(),
+            poll_interval: Duration::from_secs(1),
+        });
+    } else {
+        engine.add_collector(PangeaSource {
+            contract_id: config.contract_id.clone(),
+        });
     }
+    let replay = std::env::args().any(|arg| arg == "--replay");
+    engine.add_strategy(CyclicArb::new(config.clone(), wallet.clone(), replay));
+    engine.add_executor(LogExecutor);
+    engine.add_executor(CycleExecutor { wallet, config });
+    engine.run().await;
 }
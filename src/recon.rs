@@ -1,48 +1,246 @@
-use std::{collections::HashSet, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use crate::{
-    constants::{CONTRACT_ID, MIRA_BURN_EVENT_ID, MIRA_MINT_EVENT_ID, MIRA_SWAP_EVENT_ID},
-    tokens::get_pools,
+    decoder::{decode_pangea_json, verify_pangea_log},
     triton,
-    types::{
-        BurnEvent, BurnEventWithTx, Event, MintEvent, MintEventWithTx, PangeaLogData, SwapEvent,
-        SwapEventWithTx,
-    },
+    types::{Config, Event, EventPosition, Fees, PangeaLogData},
 };
 use chrono::Local;
 use crossbeam_channel::Sender;
 use ethers::types::U256;
 use fuels::{
     accounts::{impersonated_account::ImpersonatedAccount, wallet::WalletUnlocked},
-    programs::{
-        calls::{CallHandler, Execution},
-        responses::CallResponse,
-    },
+    programs::calls::Execution,
     types::transaction::TxPolicies,
 };
 use futures::StreamExt;
-use log::debug;
-use mira_v1::interface::PoolMetadata;
+use log::{debug, error, info};
 use pangea_client::{
     core::types::ChainId, provider::FuelProvider, query::Bound, requests::fuel::GetFuelLogsRequest,
     ClientBuilder, Format, WsProvider,
 };
 
-pub async fn stream_mira_events_pangea(tx: Sender<Event>) -> Result<(), ()> {
+const STREAM_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const STREAM_MAX_BACKOFF: Duration = Duration::from_secs(60);
+// Reads `sync_state` makes before giving up on the chain head holding still across them
+const SYNC_ATTEMPTS: usize = 5;
+
+/// Streams Mira events live, starting at `from_block` so nothing between the `sync_state`
+/// snapshot and the subscription is missed. The subscription is supervised: whenever it
+/// fails or ends, `healthy` is cleared and it is reopened from the last block seen, backing
+/// off exponentially between attempts. Only returns once the receiving side has hung up.
+pub async fn stream_mira_events_pangea(
+    tx: Sender<Event>,
+    contract_id: String,
+    from_block: u64,
+    healthy: Arc<AtomicBool>,
+) -> Result<(), ()> {
+    let mut from_block = from_block;
+    let mut backoff = STREAM_INITIAL_BACKOFF;
+
+    loop {
+        let mut last_block = from_block;
+        let mut received = false;
+        // stream realtime
+        let result = stream_mira_logs_pangea(
+            &tx,
+            &contract_id,
+            Bound::Exact(from_block as i64),
+            Bound::Subscribe,
+            |event| {
+                last_block = last_block.max(event.block_number());
+                received = true;
+                healthy.store(true, Ordering::SeqCst);
+                true
+            },
+        )
+        .await;
+        healthy.store(false, Ordering::SeqCst);
+
+        if let Err(StreamError::ReceiverClosed) = result {
+            return Err(());
+        }
+
+        // Resume from the last block we saw, replays of it are dropped by `Triton`
+        from_block = last_block;
+        if received {
+            backoff = STREAM_INITIAL_BACKOFF;
+        }
+        error!(
+            "Mira event stream ended ({:?}), reconnecting from block {} in {:?}",
+            result.err(),
+            from_block,
+            backoff
+        );
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(STREAM_MAX_BACKOFF);
+    }
+}
+
+/// Replays Mira pool creations from `from_block` to `to_block` inclusive so pools missing
+/// from `get_pools` are tracked on startup
+pub async fn backfill_mira_pools_pangea(
+    tx: Sender<Event>,
+    config: &Config,
+    from_block: u64,
+    to_block: u64,
+) -> Result<(), ()> {
+    stream_mira_logs_pangea(
+        &tx,
+        &config.contract_id,
+        Bound::Exact(from_block as i64),
+        Bound::Exact(to_block as i64),
+        |event| matches!(event, Event::MiraCreatePool(_)),
+    )
+    .await
+    .map_err(|e| error!("Pool backfill failed: {:?}", e))
+}
+
+/// Snapshots every Mira pool via `sync_state`, then rebuilds reserves by replaying swaps,
+/// mints and burns from deployment up to the snapshot height and cross-checks the two at that
+/// height. Pools the replay discovers from `CreatePool` events are snapshotted once found and
+/// the replay carried on to the new height, so they are cross-checked too. Returns the
+/// indices of pools whose replayed reserves disagree with the contract, which are left at the
+/// contract's values.
+pub async fn replay_state_pangea(
+    triton: &mut triton::Triton,
+    wallet: WalletUnlocked,
+    config: &Config,
+) -> Result<Vec<usize>, ()> {
+    let mut snapshot_block = sync_state(triton, wallet.clone(), config)
+        .await
+        .map_err(|e| error!("Failed to snapshot pools before replay: {:?}", e))?;
+    let mut contract = reserves(triton);
+
+    // Rebuild from empty pools, and let events at or below the snapshot through
+    set_reserves(triton, &HashMap::new());
+    triton.synced_block = None;
+
+    let mut from_block = 0;
+    let replayed = loop {
+        let pool_count = triton.pools.len();
+        let (tx, rx) = crossbeam_channel::unbounded::<Event>();
+        let replayed = stream_mira_logs_pangea(
+            &tx,
+            &config.contract_id,
+            Bound::Exact(from_block as i64),
+            Bound::Exact(snapshot_block as i64),
+            |_| true,
+        )
+        .await;
+        let mut events = rx.try_iter().collect::<Vec<Event>>();
+        events.sort_by_key(Event::position);
+        for event in events {
+            triton.process_event(event);
+        }
+        if replayed.is_err() || triton.pools.len() == pool_count {
+            break replayed;
+        }
+
+        // Pools created during the replay weren't in the snapshot, so take another one that
+        // covers them and carry the replay on up to it
+        info!(
+            "Replay discovered {} pools, snapshotting them too",
+            triton.pools.len() - pool_count
+        );
+        let replayed_reserves = reserves(triton);
+        let next_snapshot = sync_state(triton, wallet.clone(), config)
+            .await
+            .map_err(|e| error!("Failed to snapshot pools discovered in replay: {:?}", e))?;
+        contract = reserves(triton);
+        set_reserves(triton, &replayed_reserves);
+        triton.synced_block = None;
+        from_block = snapshot_block + 1;
+        snapshot_block = next_snapshot;
+    };
+    triton.synced_block = Some(snapshot_block);
+
+    let mut mismatches = Vec::new();
+    for (index, (reserve_0, reserve_1)) in contract {
+        // Every pool ends up at the contract's reserves, whatever the replay made of it
+        triton.stale_pools.remove(&index);
+        let Some(pool) = triton.pools.get(&index) else {
+            continue;
+        };
+        let mut pool = pool.borrow_mut();
+        if reserve_0 == pool.reserve_0 && reserve_1 == pool.reserve_1 {
+            continue;
+        }
+        if replayed.is_ok() {
+            error!(
+                "Replayed reserves for {} at block {} are ({}, {}), contract has ({}, {})",
+                pool.pool_name,
+                snapshot_block,
+                pool.reserve_0,
+                pool.reserve_1,
+                reserve_0,
+                reserve_1
+            );
+            mismatches.push(index);
+        }
+        pool.reserve_0 = reserve_0;
+        pool.reserve_1 = reserve_1;
+    }
+    replayed.map_err(|e| error!("Replay failed: {:?}", e))?;
+    mismatches.sort();
+    Ok(mismatches)
+}
+
+// Reserves of every tracked pool by index
+fn reserves(triton: &triton::Triton) -> HashMap<usize, (U256, U256)> {
+    triton
+        .pools
+        .iter()
+        .map(|(index, pool)| (*index, (pool.borrow().reserve_0, pool.borrow().reserve_1)))
+        .collect()
+}
+
+// Sets every pool to its entry in `reserves`, or empty if it has none
+fn set_reserves(triton: &triton::Triton, reserves: &HashMap<usize, (U256, U256)>) {
+    for (index, pool) in &triton.pools {
+        let (reserve_0, reserve_1) = reserves
+            .get(index)
+            .copied()
+            .unwrap_or((U256::zero(), U256::zero()));
+        let mut pool = pool.borrow_mut();
+        pool.reserve_0 = reserve_0;
+        pool.reserve_1 = reserve_1;
+    }
+}
+
+#[derive(Debug)]
+pub enum StreamError {
+    Connect(String),
+    Request(String),
+    Stream(String),
+    ReceiverClosed,
+}
+
+async fn stream_mira_logs_pangea(
+    tx: &Sender<Event>,
+    contract_id: &str,
+    from_block: Bound,
+    to_block: Bound,
+    mut filter: impl FnMut(&Event) -> bool,
+) -> Result<(), StreamError> {
     dotenvy::dotenv_override().ok();
     let client = ClientBuilder::default()
         .build::<WsProvider>()
         .await
-        .unwrap();
+        .map_err(|e| StreamError::Connect(e.to_string()))?;
 
-    // stream realtime
     let request = GetFuelLogsRequest {
-        to_block: Bound::Subscribe,
-        id__in: HashSet::from([
-            "0x2e40f2b244b98ed6b8204b3de0156c6961f98525c8162f80162fcf53eebd90e7"
-                .parse()
-                .unwrap(),
-        ]),
+        from_block,
+        to_block,
+        id__in: HashSet::from([contract_id.parse().unwrap()]),
         chains: HashSet::from([ChainId::FUEL]),
         ..Default::default()
     };
@@ -50,185 +248,179 @@ pub async fn stream_mira_events_pangea(tx: Sender<Event>) -> Result<(), ()> {
     let stream = client
         .get_fuel_logs_decoded_by_format(request, Format::JsonStream, false)
         .await
-        .unwrap();
+        .map_err(|e| StreamError::Request(e.to_string()))?;
 
     futures::pin_mut!(stream);
 
-    while let Some(Ok(data)) = stream.next().await {
-        let data: PangeaLogData = serde_json::from_slice(&data).unwrap();
-
-        let rb_value = u64::from_str_radix(&data.rb[2..], 16).expect("Invalid hexadecimal string");
-        match rb_value {
-            MIRA_SWAP_EVENT_ID => {
-                let event: SwapEvent =
-                    serde_json::from_slice(data.decoded.as_bytes()).expect("Failed to decode");
-                let event_with_tx = SwapEventWithTx {
-                    tx_id: data.transaction_hash,
-                    pool_id: event.pool_id,
-                    recipient: event.recipient,
-                    asset_0_in: event.asset_0_in,
-                    asset_1_in: event.asset_1_in,
-                    asset_0_out: event.asset_0_out,
-                    asset_1_out: event.asset_1_out,
-                };
-                debug!("Swap {:?} {:?}", data.block_number, Local::now());
-                let _ = tx.send(Event::MiraSwap(event_with_tx));
+    while let Some(data) = stream.next().await {
+        let data = data.map_err(|e| StreamError::Stream(e.to_string()))?;
+        let data: PangeaLogData = match serde_json::from_slice(&data) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("Skipping undecodable Pangea log: {:?}", e);
+                continue;
             }
-            MIRA_MINT_EVENT_ID => {
-                let event: MintEvent =
-                    serde_json::from_slice(data.decoded.as_bytes()).expect("Failed to decode");
-                let event_with_tx = MintEventWithTx {
-                    tx_id: data.transaction_hash,
-                    pool_id: event.pool_id,
-                    liquidity: event.liquidity,
-                    recipient: event.recipient,
-                    asset_0_in: event.asset_0_in,
-                    asset_1_in: event.asset_1_in,
-                };
-                debug!("Mint {:?} {:?}", event_with_tx, Local::now());
-                let _ = tx.send(Event::MiraMint(event_with_tx));
-            }
-            MIRA_BURN_EVENT_ID => {
-                let event: BurnEvent =
-                    serde_json::from_slice(data.decoded.as_bytes()).expect("Failed to decode");
-                let event_with_tx = BurnEventWithTx {
-                    tx_id: data.transaction_hash,
-                    pool_id: event.pool_id,
-                    liquidity: event.liquidity,
-                    recipient: event.recipient,
-                    asset_0_out: event.asset_0_out,
-                    asset_1_out: event.asset_1_out,
-                };
-                debug!("Burn {:?} {:?}", event_with_tx, Local::now());
-                let _ = tx.send(Event::MiraBurn(event_with_tx));
-            }
-            _ => {
-                debug!(
-                    "Not Relevant {:?} {:?}",
-                    data.transaction_hash,
-                    Local::now()
-                )
+        };
+        if let Some(event) = decode_pangea_log(data) {
+            if filter(&event) {
+                tx.send(event).map_err(|_| StreamError::ReceiverClosed)?;
             }
         }
     }
     Ok(())
 }
 
-pub async fn sync_state(triton: &mut triton::Triton, wallet: WalletUnlocked) {
+fn parse_hex_u64(value: &str) -> Option<u64> {
+    u64::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+}
+
+pub fn decode_pangea_log(data: PangeaLogData) -> Option<Event> {
+    let fields = (
+        parse_hex_u64(&data.rb),
+        parse_hex_u64(&data.block_number),
+        parse_hex_u64(&data.transaction_index),
+        parse_hex_u64(&data.log_index),
+    );
+    let (Some(rb_value), Some(block_number), Some(tx_index), Some(log_index)) = fields else {
+        error!(
+            "Skipping Pangea log in tx {} with malformed rb, block, tx or log index: {:?}",
+            data.transaction_hash,
+            (
+                &data.rb,
+                &data.block_number,
+                &data.transaction_index,
+                &data.log_index
+            )
+        );
+        return None;
+    };
+    let position = EventPosition {
+        block_number,
+        tx_index,
+        log_index,
+    };
+
+    let Some(log) = decode_pangea_json(rb_value, &data.decoded) else {
+        debug!(
+            "Not Relevant {:?} {:?}",
+            data.transaction_hash,
+            Local::now()
+        );
+        return None;
+    };
+    // Cross-check Pangea's decoding against the raw receipt data it came from
+    verify_pangea_log(&data);
+
+    let event = log.with_tx(data.transaction_hash, position);
+    debug!("{:?} {:?}", event, Local::now());
+    Some(event)
+}
+
+#[derive(Debug)]
+pub enum SyncError {
+    Provider(String),
+    Contract(String),
+    // The chain head moved during every one of `SYNC_ATTEMPTS` reads
+    ChainAdvanced { attempts: usize },
+}
+
+/// Reads fees and reserves for every tracked pool and returns the block height the snapshot
+/// reflects. Reads are retried until the chain head is unchanged across them, so the snapshot
+/// is consistent with exactly one block, and fail after `SYNC_ATTEMPTS` tries.
+pub async fn sync_state(
+    triton: &mut triton::Triton,
+    wallet: WalletUnlocked,
+    config: &Config,
+) -> Result<u64, SyncError> {
     // Get contract instance
     let address = wallet.address();
     let provider = wallet.provider();
-    let contract_id = fuels::types::ContractId::from_str(CONTRACT_ID).unwrap();
+    let contract_id = config.contract_id();
     let simulation_account: ImpersonatedAccount =
         ImpersonatedAccount::new(address.clone(), provider.cloned());
     let mira_contract = mira_v1::interface::MiraAmmContract::new(contract_id, simulation_account);
+    let provider =
+        provider.ok_or_else(|| SyncError::Provider("wallet has no provider".to_string()))?;
 
     // Get contract methods
     let contract_methods = mira_contract.methods();
+    let contract_methods = &contract_methods;
 
-    // Create multicall handlers
-    let mut fee_multi_call_handler = CallHandler::new_multi_call(wallet.clone());
-    let mut metadata_multi_call_handler = CallHandler::new_multi_call(wallet.clone());
+    let mut snapshot = None;
+    for _ in 0..SYNC_ATTEMPTS {
+        let block_before = provider
+            .latest_block_height()
+            .await
+            .map_err(|e| SyncError::Provider(e.to_string()))?;
 
-    // Get pool IDs and add them to pool manager
-    let pools = get_pools();
+        // Fees are global on Mira so a single read covers every pool
+        let fees: Fees = contract_methods
+            .fees()
+            .with_tx_policies(TxPolicies::default())
+            .simulate(Execution::StateReadOnly)
+            .await
+            .map_err(|e| SyncError::Contract(e.to_string()))?
+            .value
+            .into();
 
-    debug!("pools: {:#?}", pools.len());
+        // Read metadata for every tracked pool, including ones discovered from CreatePool events
+        let metadata_calls = triton
+            .index_mapping
+            .iter()
+            .map(|(index, pool_id)| async move {
+                let metadata = contract_methods
+                    .pool_metadata(*pool_id)
+                    .with_tx_policies(TxPolicies::default())
+                    .simulate(Execution::StateReadOnly)
+                    .await;
+                (*index, metadata)
+            });
+        let metadata_vec = futures::future::join_all(metadata_calls).await;
 
-    for pool in pools.iter() {
-        let fee_call_handler = contract_methods
-            .fees()
-            .with_tx_policies(TxPolicies::default());
-        // Add pool_metadata call to multicall handler
-        let is_stable = pool.fee_rate < U256::from(300);
-        let metadata_call_handler = contract_methods
-            .pool_metadata((pool.from, pool.to, is_stable))
-            .with_tx_policies(TxPolicies::default());
-
-        fee_multi_call_handler = fee_multi_call_handler.add_call(fee_call_handler);
-        metadata_multi_call_handler = metadata_multi_call_handler.add_call(metadata_call_handler);
+        let block_after = provider
+            .latest_block_height()
+            .await
+            .map_err(|e| SyncError::Provider(e.to_string()))?;
+        if block_before == block_after {
+            snapshot = Some((block_after as u64, fees, metadata_vec));
+            break;
+        }
+        debug!(
+            "Chain advanced from {} to {} during sync, reading again",
+            block_before, block_after
+        );
     }
+    let Some((snapshot_block, fees, metadata_vec)) = snapshot else {
+        return Err(SyncError::ChainAdvanced {
+            attempts: SYNC_ATTEMPTS,
+        });
+    };
 
-    // Execute multicall
-    let metadata_results: CallResponse<(
-        Option<PoolMetadata>,
-        Option<PoolMetadata>,
-        Option<PoolMetadata>,
-        Option<PoolMetadata>,
-        Option<PoolMetadata>,
-        Option<PoolMetadata>,
-        Option<PoolMetadata>,
-        Option<PoolMetadata>,
-        Option<PoolMetadata>,
-        Option<PoolMetadata>,
-        Option<PoolMetadata>,
-    )> = metadata_multi_call_handler
-        .simulate(Execution::StateReadOnly)
-        .await
-        .unwrap();
-
-    // Execute multicall
-    let fee_results: CallResponse<(
-        (u64, u64, u64, u64),
-        (u64, u64, u64, u64),
-        (u64, u64, u64, u64),
-        (u64, u64, u64, u64),
-        (u64, u64, u64, u64),
-        (u64, u64, u64, u64),
-        (u64, u64, u64, u64),
-        (u64, u64, u64, u64),
-        (u64, u64, u64, u64),
-        (u64, u64, u64, u64),
-        (u64, u64, u64, u64),
-    )> = fee_multi_call_handler
-        .simulate(Execution::StateReadOnly)
-        .await
-        .unwrap();
-
-    // Convert tuple response to vector
-    let metadata_vec = vec![
-        metadata_results.value.0,
-        metadata_results.value.1,
-        metadata_results.value.2,
-        metadata_results.value.3,
-        metadata_results.value.4,
-        metadata_results.value.5,
-        metadata_results.value.6,
-        metadata_results.value.7,
-        metadata_results.value.8,
-        metadata_results.value.9,
-        metadata_results.value.10,
-    ];
-
-    let fee_vec = vec![
-        fee_results.value.0,
-        fee_results.value.1,
-        fee_results.value.2,
-        fee_results.value.3,
-        fee_results.value.4,
-        fee_results.value.5,
-        fee_results.value.6,
-        fee_results.value.7,
-        fee_results.value.8,
-        fee_results.value.9,
-        fee_results.value.10,
-    ];
-
-    debug!("metadata_vec: {:#?}", metadata_vec);
-    debug!("fee_vec: {:?}", fee_vec);
+    debug!("fees: {:?}", fees);
+    debug!("pools: {:#?}", metadata_vec.len());
 
     // Process results and update pool states
-    for ((i, metadata_opt), _) in metadata_vec
-        .into_iter()
-        .enumerate()
-        .zip(fee_vec.into_iter())
-    {
+    for (i, metadata_res) in metadata_vec {
+        let metadata_opt = match metadata_res {
+            Ok(response) => response.value,
+            Err(e) => {
+                error!("Failed to read metadata for pool {}: {:?}", i, e);
+                continue;
+            }
+        };
         if let Some(metadata) = metadata_opt {
             if let Some(pool) = triton.pools.get_mut(&i) {
-                pool.borrow_mut().reserve_0 = U256::from(metadata.reserve_0);
-                pool.borrow_mut().reserve_1 = U256::from(metadata.reserve_1);
+                let mut pool = pool.borrow_mut();
+                pool.fees = fees;
+                pool.reserve_0 = U256::from(metadata.reserve_0);
+                pool.reserve_1 = U256::from(metadata.reserve_1);
+                pool.decimals_0 = metadata.decimals_0;
+                pool.decimals_1 = metadata.decimals_1;
+                triton.stale_pools.remove(&i);
             }
         }
     }
+    triton.synced_block = Some(snapshot_block);
     debug!("{:#?}", triton.pools);
+    Ok(snapshot_block)
 }
@@ -0,0 +1,131 @@
+use ethers::types::{I256, U256};
+use fuels::{tx::Receipt, types::Address};
+use log::{info, warn};
+
+use crate::{
+    bundle::{ExecutionOutcome, ExecutionStatus},
+    calc::{net_profit, NetPositiveCycle},
+    decoder::{decode_log_data, MiraLog},
+    triton::Triton,
+    types::{Config, Event, EventPosition, SwapEventWithTx},
+};
+
+/// What an executed cycle actually did, next to what it was priced to do
+#[derive(Debug)]
+pub struct Fill {
+    pub tx_id: String,
+    // Output of each hop as Mira's swap logs report it
+    pub hop_outputs: Vec<u64>,
+    // Output of each hop the cycle was priced at
+    pub predicted_outputs: Vec<U256>,
+    // Realised minus predicted output per hop
+    pub slippage: Vec<I256>,
+    // Amount of the start asset transferred back to us
+    pub received: Option<u64>,
+    // Revert or panic reported in the receipts
+    pub failure: Option<String>,
+    // Pools whose reserves were updated from the swap logs
+    pub updated_pools: Vec<usize>,
+}
+
+/// Parses the receipts of an executed cycle, compares each hop with the prediction in
+/// `cycle.swap_amounts` and applies the swaps to `triton` so reserves match the chain
+/// without waiting for the feed. A reverted cycle is reported but changes no reserves.
+/// Returns None if nothing was sent.
+pub fn reconcile_fill(
+    triton: &mut Triton,
+    cycle: &NetPositiveCycle,
+    outcome: &ExecutionOutcome,
+    recipient: Address,
+    config: &Config,
+) -> Option<Fill> {
+    let tx_id = outcome.tx_id.clone()?;
+    let contract_id = config.contract_id();
+
+    let mut swaps = Vec::new();
+    let mut received = None;
+    let mut failure = None;
+    for (log_index, receipt) in outcome.receipts.iter().enumerate() {
+        match receipt {
+            Receipt::LogData {
+                id,
+                rb,
+                data: Some(data),
+                ..
+            } if *id == contract_id => {
+                // Position isn't known until the block is seen, `handle_swap` doesn't read it
+                let position = EventPosition {
+                    block_number: 0,
+                    tx_index: 0,
+                    log_index: log_index as u64,
+                };
+                if let Some(log @ MiraLog::Swap(_)) = decode_log_data(*rb, data) {
+                    if let Event::MiraSwap(swap) = log.with_tx(tx_id.clone(), position) {
+                        swaps.push(swap);
+                    }
+                }
+            }
+            Receipt::TransferOut {
+                to,
+                amount,
+                asset_id,
+                ..
+            } if *to == recipient && *asset_id == cycle.token_in => {
+                received = Some(received.unwrap_or(0) + amount);
+            }
+            Receipt::Revert { id, ra, .. } => {
+                failure = Some(format!("{:#x} reverted with {:#x}", id, ra));
+            }
+            Receipt::Panic { id, reason, .. } => {
+                failure = Some(format!("{:#x} panicked with {:?}", id, reason.reason()));
+            }
+            _ => {}
+        }
+    }
+
+    let hop_outputs = swaps.iter().map(swap_output).collect::<Vec<u64>>();
+    let predicted_outputs = cycle
+        .swap_amounts
+        .iter()
+        .skip(1)
+        .copied()
+        .collect::<Vec<U256>>();
+    let slippage = hop_outputs
+        .iter()
+        .zip(&predicted_outputs)
+        .map(|(realised, predicted)| net_profit(*predicted, U256::from(*realised)))
+        .collect::<Vec<I256>>();
+    if hop_outputs.len() != predicted_outputs.len() {
+        warn!(
+            "Fill {} has {} swap logs for a {}-hop cycle",
+            tx_id,
+            hop_outputs.len(),
+            predicted_outputs.len()
+        );
+    }
+
+    // Swaps logged before a revert were rolled back with it
+    let updated_pools = match &outcome.status {
+        ExecutionStatus::Reverted(reason) => {
+            failure.get_or_insert_with(|| reason.clone());
+            Vec::new()
+        }
+        _ => triton.apply_own_swaps(&tx_id, &swaps),
+    };
+    let fill = Fill {
+        tx_id,
+        hop_outputs,
+        predicted_outputs,
+        slippage,
+        received,
+        failure,
+        updated_pools,
+    };
+    info!("Reconciled fill: {:?}", fill);
+    Some(fill)
+}
+
+// Amount a swap paid out, only one side of a Mira swap is ever non-zero
+fn swap_output(swap: &SwapEventWithTx) -> u64 {
+    swap.asset_0_out + swap.asset_1_out
+}
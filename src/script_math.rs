@@ -0,0 +1,565 @@
+use std::cell::RefCell;
+
+use ethers::types::U256;
+use fuels::types::AssetId;
+
+use crate::{constants::BASIS_POINTS_DENOMINATOR, types::Pool};
+
+// Replica of `swap-script/src/math.sw`. Intermediates are u256 like in Sway and every
+// operation the VM would revert on (overflow, underflow, division by zero, a result that
+// doesn't fit its u64) returns None instead, so Some is exactly what the script computes.
+
+fn one_e_18() -> U256 {
+    U256::exp10(18)
+}
+
+fn to_u64(value: U256) -> Option<u64> {
+    if value > U256::from(u64::MAX) {
+        return None;
+    }
+    Some(value.low_u64())
+}
+
+pub fn pow_decimals(decimals: u8) -> Option<U256> {
+    U256::from(10).checked_pow(U256::from(decimals))
+}
+
+fn adjust(amount: U256, pow_decimals: U256) -> Option<U256> {
+    amount.checked_mul(one_e_18())?.checked_div(pow_decimals)
+}
+
+pub fn rounding_up_division(nominator: U256, denominator: U256) -> Option<U256> {
+    let rounding_down_division_result = nominator.checked_div(denominator)?;
+    if (nominator % denominator).is_zero() {
+        Some(rounding_down_division_result)
+    } else {
+        rounding_down_division_result.checked_add(U256::one())
+    }
+}
+
+pub fn calculate_fee_to_subtract(amount: u64, fee_bp: u64) -> Option<u64> {
+    let nominator = U256::from(amount) * U256::from(fee_bp);
+    let fee = rounding_up_division(nominator, U256::from(BASIS_POINTS_DENOMINATOR))?;
+    to_u64(fee)
+}
+
+pub fn calculate_fee_to_add(amount: u64, fee_bp: u64) -> Option<u64> {
+    let nominator = U256::from(amount) * U256::from(fee_bp);
+    let denominator = U256::from(BASIS_POINTS_DENOMINATOR).checked_sub(U256::from(fee_bp))?;
+    let fee = rounding_up_division(nominator, denominator)?;
+    to_u64(fee)
+}
+
+pub fn subtract_fee(amount: u64, fee_bp: u64) -> Option<u64> {
+    amount.checked_sub(calculate_fee_to_subtract(amount, fee_bp)?)
+}
+
+pub fn add_fee(amount: u64, fee_bp: u64) -> Option<u64> {
+    amount.checked_add(calculate_fee_to_add(amount, fee_bp)?)
+}
+
+pub fn get_amount_out(
+    is_stable: bool,
+    reserve_in: U256,
+    reserve_out: U256,
+    pow_decimals_in: U256,
+    pow_decimals_out: U256,
+    input_amount: U256,
+) -> Option<U256> {
+    if is_stable {
+        let xy = k(
+            true,
+            reserve_in,
+            reserve_out,
+            pow_decimals_in,
+            pow_decimals_out,
+        )?;
+
+        let amount_in_adjusted = adjust(input_amount, pow_decimals_in)?;
+        let reserve_in_adjusted = adjust(reserve_in, pow_decimals_in)?;
+        let reserve_out_adjusted = adjust(reserve_out, pow_decimals_out)?;
+        let y = reserve_out_adjusted.checked_sub(get_y(
+            amount_in_adjusted.checked_add(reserve_in_adjusted)?,
+            xy,
+            reserve_out_adjusted,
+        )?)?;
+        y.checked_mul(pow_decimals_out)?.checked_div(one_e_18())
+    } else {
+        input_amount
+            .checked_mul(reserve_out)?
+            .checked_div(reserve_in.checked_add(input_amount)?)
+    }
+}
+
+pub fn get_amount_in(
+    is_stable: bool,
+    reserve_in: U256,
+    reserve_out: U256,
+    pow_decimals_in: U256,
+    pow_decimals_out: U256,
+    output_amount: U256,
+) -> Option<U256> {
+    if is_stable {
+        let xy = k(
+            true,
+            reserve_in,
+            reserve_out,
+            pow_decimals_in,
+            pow_decimals_out,
+        )?;
+
+        let amount_out_adjusted = adjust(output_amount, pow_decimals_out)?;
+        let reserve_in_adjusted = adjust(reserve_in, pow_decimals_in)?;
+        let reserve_out_adjusted = adjust(reserve_out, pow_decimals_out)?;
+        let y = get_y(
+            reserve_out_adjusted.checked_sub(amount_out_adjusted)?,
+            xy,
+            reserve_in_adjusted,
+        )?
+        .checked_sub(reserve_in_adjusted)?;
+        rounding_up_division(y.checked_mul(pow_decimals_in)?, one_e_18())
+    } else {
+        rounding_up_division(
+            output_amount.checked_mul(reserve_in)?,
+            reserve_out.checked_sub(output_amount)?,
+        )
+    }
+}
+
+/// What the swap script's `get_amounts_out` returns for `amount_in` of `asset_in` routed
+/// through `pools`, starting with the input itself, or None where the script would revert
+pub fn get_amounts_out(
+    amount_in: u64,
+    asset_in: AssetId,
+    pools: &[&RefCell<Pool>],
+) -> Option<Vec<(u64, AssetId)>> {
+    if pools.is_empty() {
+        return None;
+    }
+
+    let mut amounts = vec![(amount_in, asset_in)];
+    for (i, pool) in pools.iter().enumerate() {
+        let (amount_in, asset_in) = amounts[i];
+        amounts.push(get_amount_out_for_hop(&pool.borrow(), asset_in, amount_in)?);
+    }
+    Some(amounts)
+}
+
+/// One hop of `get_amounts_out`: the fee is taken off `amount_in` of `asset_in`, then the
+/// rest is swapped through `pool`. Returns the amount and asset received.
+pub fn get_amount_out_for_hop(
+    pool: &Pool,
+    asset_in: AssetId,
+    amount_in: u64,
+) -> Option<(u64, AssetId)> {
+    let amount_in = U256::from(subtract_fee(amount_in, pool.fees.total(pool.is_stable))?);
+    let amount_out = if asset_in == pool.from {
+        get_amount_out(
+            pool.is_stable,
+            pool.reserve_0,
+            pool.reserve_1,
+            pow_decimals(pool.decimals_0)?,
+            pow_decimals(pool.decimals_1)?,
+            amount_in,
+        )?
+    } else {
+        get_amount_out(
+            pool.is_stable,
+            pool.reserve_1,
+            pool.reserve_0,
+            pow_decimals(pool.decimals_1)?,
+            pow_decimals(pool.decimals_0)?,
+            amount_in,
+        )?
+    };
+
+    let asset_out = if pool.from == asset_in {
+        pool.to
+    } else {
+        pool.from
+    };
+    Some((to_u64(amount_out)?, asset_out))
+}
+
+/// What the swap script's `get_amounts_in` returns for receiving `amount_out` of `asset_out`
+/// from `pools`, walked from the last pool back, or None where the script would revert
+pub fn get_amounts_in(
+    amount_out: u64,
+    asset_out: AssetId,
+    pools: &[&RefCell<Pool>],
+) -> Option<Vec<(u64, AssetId)>> {
+    if pools.is_empty() {
+        return None;
+    }
+
+    let mut amounts = vec![(amount_out, asset_out)];
+    for (i, pool) in pools.iter().rev().enumerate() {
+        let pool = pool.borrow();
+        let (amount_out, asset_out) = amounts[i];
+        let amount_out = U256::from(amount_out);
+        let amount_in = if asset_out == pool.from {
+            get_amount_in(
+                pool.is_stable,
+                pool.reserve_1,
+                pool.reserve_0,
+                pow_decimals(pool.decimals_1)?,
+                pow_decimals(pool.decimals_0)?,
+                amount_out,
+            )?
+        } else {
+            get_amount_in(
+                pool.is_stable,
+                pool.reserve_0,
+                pool.reserve_1,
+                pow_decimals(pool.decimals_0)?,
+                pow_decimals(pool.decimals_1)?,
+                amount_out,
+            )?
+        };
+
+        let asset_in = if pool.from == asset_out {
+            pool.to
+        } else {
+            pool.from
+        };
+        let amount_in_with_fee = add_fee(to_u64(amount_in)?, pool.fees.total(pool.is_stable))?;
+        amounts.push((amount_in_with_fee, asset_in));
+    }
+    Some(amounts)
+}
+
+fn k(
+    is_stable: bool,
+    x: U256,
+    y: U256,
+    pow_decimals_x: U256,
+    pow_decimals_y: U256,
+) -> Option<U256> {
+    if is_stable {
+        let x = adjust(x, pow_decimals_x)?;
+        let y = adjust(y, pow_decimals_y)?;
+        let a = x.checked_mul(y)? / one_e_18();
+        let b = (x.checked_mul(x)? / one_e_18()).checked_add(y.checked_mul(y)? / one_e_18())?;
+        a.checked_mul(b) // x3y+y3x >= k
+    } else {
+        x.checked_mul(y) // xy >= k
+    }
+}
+
+// `v * v / ONE_E_18 * v / ONE_E_18`, evaluated left to right like the script
+fn cube(v: U256) -> Option<U256> {
+    let squared = v.checked_mul(v)? / one_e_18();
+    Some(squared.checked_mul(v)? / one_e_18())
+}
+
+fn f(x_0: U256, y: U256) -> Option<U256> {
+    x_0.checked_mul(cube(y)?)?
+        .checked_add(cube(x_0)?.checked_mul(y)?)
+}
+
+fn d(x_0: U256, y: U256) -> Option<U256> {
+    let y_squared = y.checked_mul(y)? / one_e_18();
+    (U256::from(3).checked_mul(x_0)?.checked_mul(y_squared)? / one_e_18()).checked_add(cube(x_0)?)
+}
+
+fn get_y(x_0: U256, xy: U256, y: U256) -> Option<U256> {
+    let mut y = y;
+    for _ in 0..255 {
+        let y_prev = y;
+        let k = f(x_0, y)?;
+        if k < xy {
+            let dy = (xy - k).checked_div(d(x_0, y)?)?;
+            y = y.checked_add(dy)?;
+        } else {
+            let dy = (k - xy).checked_div(d(x_0, y)?)?;
+            y = y.checked_sub(dy)?;
+        }
+        let delta = if y > y_prev { y - y_prev } else { y_prev - y };
+        if delta <= U256::one() {
+            return Some(y);
+        }
+    }
+    Some(y)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use ethers::types::U256;
+    use fuels::types::AssetId;
+    use proptest::prelude::*;
+
+    use super::{
+        add_fee, calculate_fee_to_add, calculate_fee_to_subtract, get_amount_out,
+        get_amount_out_for_hop, get_amounts_in, get_amounts_out, pow_decimals,
+        rounding_up_division, subtract_fee,
+    };
+    use crate::types::{Fees, Pool};
+
+    fn asset(index: usize) -> AssetId {
+        AssetId::new([index as u8 + 1; 32])
+    }
+
+    // A pool between assets `hop` and `hop + 1`, either way round. Fuel assets have at most
+    // 9 decimals, past that the stable solver's ±1 shows up in raw units.
+    fn pool_strategy(hop: usize) -> impl Strategy<Value = Pool> {
+        (
+            any::<bool>(),
+            any::<bool>(),
+            1_000_000_000u64..1_000_000_000_000_000,
+            1_000_000_000u64..1_000_000_000_000_000,
+            6u8..=9,
+            6u8..=9,
+            0u64..=80,
+            0u64..=20,
+        )
+            .prop_map(
+                move |(
+                    is_stable,
+                    flipped,
+                    reserve_0,
+                    reserve_1,
+                    decimals_0,
+                    decimals_1,
+                    lp_fee,
+                    protocol_fee,
+                )| {
+                    let (from, to) = if flipped {
+                        (asset(hop + 1), asset(hop))
+                    } else {
+                        (asset(hop), asset(hop + 1))
+                    };
+                    Pool {
+                        pool_name: format!("{}/{}", hop, hop + 1),
+                        from,
+                        to,
+                        is_stable,
+                        reserve_0: U256::from(reserve_0),
+                        reserve_1: U256::from(reserve_1),
+                        decimals_0,
+                        decimals_1,
+                        fees: Fees {
+                            lp_fee_volatile: lp_fee,
+                            lp_fee_stable: lp_fee,
+                            protocol_fee_volatile: protocol_fee,
+                            protocol_fee_stable: protocol_fee,
+                        },
+                    }
+                },
+            )
+    }
+
+    // One to three pools routing asset 0 to asset `hops`
+    fn route_strategy() -> impl Strategy<Value = Vec<RefCell<Pool>>> {
+        (1usize..=3).prop_flat_map(|hops| {
+            (0..hops)
+                .map(pool_strategy)
+                .collect::<Vec<_>>()
+                .prop_map(|pools| pools.into_iter().map(RefCell::new).collect())
+        })
+    }
+
+    fn pool(is_stable: bool, reserves: (u64, u64), decimals: (u8, u8), fee: u64) -> Pool {
+        Pool {
+            pool_name: "0/1".to_string(),
+            from: asset(0),
+            to: asset(1),
+            is_stable,
+            reserve_0: U256::from(reserves.0),
+            reserve_1: U256::from(reserves.1),
+            decimals_0: decimals.0,
+            decimals_1: decimals.1,
+            fees: Fees {
+                lp_fee_volatile: fee,
+                lp_fee_stable: fee,
+                protocol_fee_volatile: 0,
+                protocol_fee_stable: 0,
+            },
+        }
+    }
+
+    // The vectors below are the ones commented out at the bottom of `math.sw`
+
+    #[test]
+    fn pow_decimals_vectors() {
+        assert_eq!(pow_decimals(0), Some(U256::one()));
+        assert_eq!(pow_decimals(6), Some(U256::from(1_000_000)));
+        assert_eq!(pow_decimals(9), Some(U256::from(1_000_000_000)));
+        assert_eq!(pow_decimals(18), Some(U256::exp10(18)));
+        assert_eq!(pow_decimals(72), Some(U256::exp10(72)));
+    }
+
+    #[test]
+    fn calculate_fee_to_subtract_vectors() {
+        assert_eq!(calculate_fee_to_subtract(10, 1), Some(1));
+        assert_eq!(calculate_fee_to_subtract(10000, 1), Some(1));
+        assert_eq!(calculate_fee_to_subtract(20000, 1), Some(2));
+        assert_eq!(calculate_fee_to_subtract(20000, 10), Some(20));
+        assert_eq!(calculate_fee_to_subtract(20001, 10), Some(21));
+        assert_eq!(calculate_fee_to_subtract(100, 10000), Some(100));
+        assert_eq!(calculate_fee_to_subtract(u64::MAX, 10000), Some(u64::MAX));
+    }
+
+    #[test]
+    fn added_fee_is_subtracted_back() {
+        for amount in [10, 100, 1000, 10000, 20000, 20001, u64::MAX / 2] {
+            for fee in [1, 10, 100, 1000, 5000] {
+                let with_fee = add_fee(amount, fee).unwrap();
+                assert_eq!(
+                    with_fee - calculate_fee_to_subtract(with_fee, fee).unwrap(),
+                    amount
+                );
+                assert_eq!(subtract_fee(with_fee, fee), Some(amount));
+            }
+        }
+        // The whole amount as a fee leaves nothing to divide by
+        assert_eq!(calculate_fee_to_add(100, 10000), None);
+    }
+
+    #[test]
+    fn rounding_up_division_vectors() {
+        let cases = [
+            (1000, 1000, 1),
+            (1000, 1, 1000),
+            (1000, 5, 200),
+            (1000, 2000, 1),
+            (9, 3, 3),
+            (10, 3, 4),
+            (11, 3, 4),
+            (12, 3, 4),
+        ];
+        for (nominator, denominator, expected) in cases {
+            assert_eq!(
+                rounding_up_division(U256::from(nominator), U256::from(denominator)),
+                Some(U256::from(expected))
+            );
+        }
+        assert_eq!(
+            rounding_up_division(U256::exp10(72), U256::exp10(12)),
+            Some(U256::exp10(60))
+        );
+        assert_eq!(
+            rounding_up_division(U256::exp10(72) + 1, U256::exp10(12)),
+            Some(U256::exp10(60) + 1)
+        );
+        assert_eq!(rounding_up_division(U256::one(), U256::zero()), None);
+    }
+
+    // Expected outputs are the floor of the exact solution of x³y + y³x = k for the new
+    // reserve, solved by bisection at 80 significant digits rather than with Newton's method
+    #[test]
+    fn stable_amount_out_vectors() {
+        let cases: [(u64, u64, u8, u8, u64, u64); 6] = [
+            (
+                1_000_000_000_000,
+                1_000_000_000_000,
+                6,
+                6,
+                1_000_000,
+                999_999,
+            ),
+            (
+                1_000_000_000_000,
+                1_000_000_000_000,
+                6,
+                6,
+                100_000_000_000,
+                99_950_051_800,
+            ),
+            (
+                5_000_000_000_000,
+                2_000_000_000_000,
+                6,
+                6,
+                1_000_000_000,
+                853_932_834,
+            ),
+            (
+                1_000_000_000_000_000,
+                1_000_000_000_000,
+                9,
+                6,
+                1_000_000_000_000,
+                999_999_999,
+            ),
+            (
+                1_000_000_000_000,
+                1_000_000_000_000_000,
+                6,
+                9,
+                1_000_000_000,
+                999_999_999_500,
+            ),
+            (
+                200_000_000_000_000,
+                300_000_000_000_000,
+                9,
+                9,
+                5_000_000_000_000,
+                5_069_197_457_930,
+            ),
+        ];
+        for (reserve_in, reserve_out, decimals_in, decimals_out, amount_in, expected) in cases {
+            let amount_out = get_amount_out(
+                true,
+                U256::from(reserve_in),
+                U256::from(reserve_out),
+                pow_decimals(decimals_in).unwrap(),
+                pow_decimals(decimals_out).unwrap(),
+                U256::from(amount_in),
+            );
+            assert_eq!(
+                amount_out,
+                Some(U256::from(expected)),
+                "{} in against {}/{}",
+                amount_in,
+                reserve_in,
+                reserve_out
+            );
+        }
+    }
+
+    #[test]
+    fn hop_takes_fee_before_swapping() {
+        // 1_000_000 less 30 bp is 997_000, then 997_000 * 2e12 / (1e12 + 997_000)
+        let volatile = pool(false, (1_000_000_000_000, 2_000_000_000_000), (9, 9), 30);
+        assert_eq!(
+            get_amount_out_for_hop(&volatile, asset(0), 1_000_000),
+            Some((1_993_998, asset(1)))
+        );
+        assert_eq!(
+            get_amount_out_for_hop(&volatile, asset(1), 1_000_000),
+            Some((498_499, asset(0)))
+        );
+
+        let stable = pool(true, (1_000_000_000_000, 1_000_000_000_000), (6, 6), 5);
+        assert_eq!(
+            get_amount_out_for_hop(&stable, asset(0), 1_000_000),
+            Some((999_499, asset(1)))
+        );
+        // Nothing in and an empty pool divides zero by zero, which reverts
+        let empty = pool(false, (0, 0), (9, 9), 0);
+        assert_eq!(get_amount_out_for_hop(&empty, asset(0), 0), None);
+    }
+
+    proptest! {
+        #[test]
+        fn get_amounts_in_round_trips(pools in route_strategy(), amount_out in 1u64..1_000_000_000) {
+            let hops = pools.len();
+            let pairs = pools.iter().collect::<Vec<&RefCell<Pool>>>();
+            // Nothing to round trip where the script would revert
+            let Some(amounts_in) = get_amounts_in(amount_out, asset(hops), &pairs) else {
+                return Ok(());
+            };
+            let (amount_in, asset_in) = *amounts_in.last().unwrap();
+            prop_assert_eq!(asset_in, asset(0));
+
+            let amounts_out = get_amounts_out(amount_in, asset_in, &pairs);
+            prop_assert!(amounts_out.is_some(), "{} in reverts going forward", amount_in);
+            let (received, asset_out) = *amounts_out.unwrap().last().unwrap();
+            prop_assert_eq!(asset_out, asset(hops));
+            prop_assert!(received >= amount_out, "asked for {}, received {}", amount_out, received);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::str::FromStr;
+
+use fuels::{
+    accounts::{provider::Provider, wallet::WalletUnlocked},
+    crypto::SecretKey,
+};
+use log::info;
+
+// Fuel's default account path, used when `DERIVATION_PATH` is unset
+const DEFAULT_DERIVATION_PATH: &str = "m/44'/1179993420'/0'/0/0";
+
+/// Why a signer couldn't be loaded. Messages name the source that failed, never key material.
+#[derive(Debug)]
+pub enum SignerError {
+    // None of KEYSTORE_PATH, MNEMONIC or PRIVATE_KEY is set
+    Missing,
+    InvalidPrivateKey,
+    Keystore(String),
+    Mnemonic(String),
+    WrongNetwork { expected: u64, actual: u64 },
+}
+
+/// Where the signing key came from, safe to log
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeySource {
+    Keystore,
+    Mnemonic,
+    PrivateKey,
+}
+
+/// Loads the trading wallet from the environment (including `.env`), checking `provider` is
+/// on the expected chain first. Sources are tried in order:
+/// - `KEYSTORE_PATH` with `KEYSTORE_PASSWORD`, a fuels JSON keystore
+/// - `MNEMONIC` with an optional `DERIVATION_PATH`
+/// - `PRIVATE_KEY`, hex encoded
+pub fn load_wallet(
+    provider: Provider,
+    expected_chain_id: u64,
+) -> Result<(WalletUnlocked, KeySource), SignerError> {
+    dotenvy::dotenv_override().ok();
+    validate_network(&provider, expected_chain_id)?;
+
+    let (wallet, source) = if let Ok(path) = std::env::var("KEYSTORE_PATH") {
+        let password = std::env::var("KEYSTORE_PASSWORD").unwrap_or_default();
+        let wallet = WalletUnlocked::load_keystore(&path, password, Some(provider))
+            .map_err(|e| SignerError::Keystore(format!("{}: {}", path, e)))?;
+        (wallet, KeySource::Keystore)
+    } else if let Ok(phrase) = std::env::var("MNEMONIC") {
+        let path = std::env::var("DERIVATION_PATH")
+            .unwrap_or_else(|_| DEFAULT_DERIVATION_PATH.to_string());
+        // Don't pass fuels' error through in case it quotes the phrase
+        let wallet =
+            WalletUnlocked::new_from_mnemonic_phrase_with_path(&phrase, Some(provider), &path)
+                .map_err(|_| SignerError::Mnemonic(format!("derivation path {}", path)))?;
+        (wallet, KeySource::Mnemonic)
+    } else if let Ok(private_key) = std::env::var("PRIVATE_KEY") {
+        let secret_key =
+            SecretKey::from_str(private_key.trim()).map_err(|_| SignerError::InvalidPrivateKey)?;
+        (
+            WalletUnlocked::new_from_private_key(secret_key, Some(provider)),
+            KeySource::PrivateKey,
+        )
+    } else {
+        return Err(SignerError::Missing);
+    };
+
+    info!("Loaded wallet {} from {:?}", wallet.address(), source);
+    Ok((wallet, source))
+}
+
+fn validate_network(provider: &Provider, expected: u64) -> Result<(), SignerError> {
+    let actual = *provider.chain_id();
+    if actual != expected {
+        return Err(SignerError::WrongNetwork { expected, actual });
+    }
+    Ok(())
+}
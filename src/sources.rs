@@ -0,0 +1,219 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{
+    decoder::decode_log_data,
+    recon::stream_mira_events_pangea,
+    types::{Event, EventPosition},
+};
+use crossbeam_channel::Sender;
+use fuels::{
+    accounts::provider::Provider,
+    tx::Receipt,
+    types::{tx_status::TxStatus, ContractId},
+};
+use log::{debug, error};
+
+/// A feed of Mira events. Implementations push every event from `from_block` onward into
+/// `tx`, keep `healthy` up to date while doing so, and only return once the receiver hangs up.
+pub trait EventSource {
+    fn run(
+        self,
+        tx: Sender<Event>,
+        from_block: u64,
+        healthy: Arc<AtomicBool>,
+    ) -> impl Future<Output = Result<(), ()>> + Send;
+}
+
+/// Pangea's indexer, subscribed over websocket
+pub struct PangeaSource {
+    pub contract_id: String,
+}
+
+impl EventSource for PangeaSource {
+    fn run(
+        self,
+        tx: Sender<Event>,
+        from_block: u64,
+        healthy: Arc<AtomicBool>,
+    ) -> impl Future<Output = Result<(), ()>> + Send {
+        stream_mira_events_pangea(tx, self.contract_id, from_block, healthy)
+    }
+}
+
+/// Polls a Fuel node block by block and reads Mira's log receipts straight from it
+pub struct FuelNodeSource {
+    pub provider: Provider,
+    pub contract_id: ContractId,
+    pub poll_interval: Duration,
+}
+
+impl EventSource for FuelNodeSource {
+    async fn run(
+        self,
+        tx: Sender<Event>,
+        from_block: u64,
+        healthy: Arc<AtomicBool>,
+    ) -> Result<(), ()> {
+        let contract_id = self.contract_id;
+        let mut next_block = from_block as u32;
+
+        loop {
+            let latest_block = match self.provider.latest_block_height().await {
+                Ok(height) => height,
+                Err(e) => {
+                    healthy.store(false, Ordering::SeqCst);
+                    error!("Failed to read latest block from node: {:?}", e);
+                    tokio::time::sleep(self.poll_interval).await;
+                    continue;
+                }
+            };
+
+            while next_block <= latest_block {
+                let events = match self.block_events(next_block, contract_id).await {
+                    Ok(events) => events,
+                    Err(e) => {
+                        healthy.store(false, Ordering::SeqCst);
+                        error!("Failed to read block {} from node: {:?}", next_block, e);
+                        break;
+                    }
+                };
+                for event in events {
+                    tx.send(event).map_err(|_| ())?;
+                }
+                healthy.store(true, Ordering::SeqCst);
+                next_block += 1;
+            }
+
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+}
+
+impl FuelNodeSource {
+    async fn block_events(
+        &self,
+        height: u32,
+        contract_id: ContractId,
+    ) -> fuels::types::errors::Result<Vec<Event>> {
+        let Some(block) = self.provider.block_by_height(height.into()).await? else {
+            return Ok(Vec::new());
+        };
+
+        let mut events = Vec::new();
+        for (tx_index, tx_id) in block.transactions.iter().enumerate() {
+            let status = self.provider.tx_status(tx_id).await?;
+            let tx_id = format!("{:#x}", tx_id);
+            events.extend(tx_events(status, tx_id, height, tx_index, contract_id));
+        }
+        Ok(events)
+    }
+}
+
+// Mira events logged by one transaction of block `height`. A reverted transaction still
+// carries the logs it emitted before failing, but none of them took effect.
+fn tx_events(
+    status: TxStatus,
+    tx_id: String,
+    height: u32,
+    tx_index: usize,
+    contract_id: ContractId,
+) -> Vec<Event> {
+    let TxStatus::Success { receipts, .. } = status else {
+        debug!("Skipping unsuccessful tx {}", tx_id);
+        return Vec::new();
+    };
+
+    let mut events = Vec::new();
+    for (log_index, receipt) in receipts.iter().enumerate() {
+        if let Receipt::LogData {
+            id,
+            rb,
+            data: Some(data),
+            ..
+        } = receipt
+        {
+            if *id != contract_id {
+                continue;
+            }
+            let position = EventPosition {
+                block_number: height as u64,
+                tx_index: tx_index as u64,
+                log_index: log_index as u64,
+            };
+            match decode_log_data(*rb, data) {
+                Some(log) => events.push(log.with_tx(tx_id.clone(), position)),
+                None => debug!("Not Relevant {:?}", tx_id),
+            }
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use fuels::{
+        core::{codec::ABIEncoder, traits::Tokenizable},
+        tx::Receipt,
+        types::{tx_status::TxStatus, Address, AssetId, Bytes32, ContractId, Identity},
+    };
+    use mira_v1::interface::SwapEvent;
+
+    use super::tx_events;
+    use crate::{constants::MIRA_SWAP_EVENT_ID, types::Event};
+
+    fn swap_receipts(contract_id: ContractId) -> Vec<Receipt> {
+        let swap = SwapEvent {
+            pool_id: (AssetId::new([1; 32]), AssetId::new([2; 32]), false),
+            recipient: Identity::Address(Address::new([3; 32])),
+            asset_0_in: 1_000,
+            asset_1_in: 0,
+            asset_0_out: 0,
+            asset_1_out: 1_990,
+        };
+        let data = ABIEncoder::default().encode(&[swap.into_token()]).unwrap();
+        vec![Receipt::LogData {
+            id: contract_id,
+            ra: 0,
+            rb: MIRA_SWAP_EVENT_ID,
+            ptr: 0,
+            len: data.len() as u64,
+            digest: Bytes32::zeroed(),
+            pc: 0,
+            is: 0,
+            data: Some(data),
+        }]
+    }
+
+    #[test]
+    fn successful_tx_yields_its_logs() {
+        let contract_id = ContractId::new([9; 32]);
+        let status = TxStatus::Success {
+            receipts: swap_receipts(contract_id),
+        };
+        let events = tx_events(status, "0xaa".to_string(), 7, 0, contract_id);
+        assert_eq!(events.len(), 1);
+        let Event::MiraSwap(swap) = &events[0] else {
+            panic!("expected a swap, got {:?}", events[0]);
+        };
+        assert_eq!(swap.block_number, 7);
+        assert_eq!(swap.asset_1_out, 1_990);
+    }
+
+    #[test]
+    fn reverted_tx_yields_nothing() {
+        let contract_id = ContractId::new([9; 32]);
+        let status = TxStatus::Revert {
+            receipts: swap_receipts(contract_id),
+            reason: "Revert(0)".to_string(),
+            revert_id: 0,
+        };
+        assert!(tx_events(status, "0xaa".to_string(), 7, 0, contract_id).is_empty());
+    }
+}
@@ -1 +1,164 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
 
+use crossbeam_channel::Sender;
+use fuels::accounts::wallet::WalletUnlocked;
+use futures::future::LocalBoxFuture;
+use log::{error, info};
+
+use crate::{
+    bundle::{self, ExecutionOutcome},
+    recon::SyncError,
+    sources::EventSource,
+    types::{Action, Config, Event},
+};
+
+/// Turns the event feed into actions. Strategies share one feed, so each sees every event.
+pub trait Strategy {
+    /// Builds the strategy's state from chain and returns the block it's synced to
+    fn sync_state(&mut self) -> LocalBoxFuture<'_, Result<u64, SyncError>>;
+
+    fn process_event(&mut self, event: Event) -> LocalBoxFuture<'_, Vec<Action>>;
+
+    /// Called with what an executor did with one of this strategy's actions
+    fn on_execution<'a>(
+        &'a mut self,
+        _action: &'a Action,
+        _outcome: &'a ExecutionOutcome,
+    ) -> LocalBoxFuture<'a, ()> {
+        Box::pin(async {})
+    }
+}
+
+/// Carries out actions. Every action is offered to every executor, which returns None for
+/// actions it doesn't handle or that don't produce an outcome.
+pub trait Executor {
+    fn execute<'a>(
+        &'a mut self,
+        action: &'a Action,
+    ) -> LocalBoxFuture<'a, Option<ExecutionOutcome>>;
+}
+
+/// Sends cycles through the swap script
+pub struct CycleExecutor {
+    pub wallet: WalletUnlocked,
+    pub config: Config,
+}
+
+impl Executor for CycleExecutor {
+    fn execute<'a>(
+        &'a mut self,
+        action: &'a Action,
+    ) -> LocalBoxFuture<'a, Option<ExecutionOutcome>> {
+        Box::pin(async move {
+            let Action::ExecuteCycle(cycle) = action else {
+                return None;
+            };
+            let now = Instant::now();
+            let outcome = bundle::execute_cycle(&self.wallet, &self.config, cycle).await;
+            let elapsed = now.elapsed().as_millis();
+            println!("Cycle execution took {}ms: {:?}", elapsed, outcome);
+            Some(outcome)
+        })
+    }
+}
+
+/// Prints `Action::Log` messages
+pub struct LogExecutor;
+
+impl Executor for LogExecutor {
+    fn execute<'a>(
+        &'a mut self,
+        action: &'a Action,
+    ) -> LocalBoxFuture<'a, Option<ExecutionOutcome>> {
+        Box::pin(async move {
+            if let Action::Log(message) = action {
+                println!("{}", message);
+            }
+            None
+        })
+    }
+}
+
+// Starts an event source once the block to stream from is known
+type Collector = Box<dyn FnOnce(Sender<Event>, u64, Arc<AtomicBool>)>;
+
+/// Runs collectors → strategies → executors: syncs every strategy, streams events from the
+/// oldest block any of them is synced to, and executes whatever actions they return
+#[derive(Default)]
+pub struct Engine {
+    collectors: Vec<Collector>,
+    strategies: Vec<Box<dyn Strategy>>,
+    executors: Vec<Box<dyn Executor>>,
+}
+
+impl Engine {
+    pub fn add_collector(&mut self, source: impl EventSource + Send + 'static) {
+        self.collectors
+            .push(Box::new(move |tx, from_block, healthy| {
+                tokio::spawn(async move {
+                    if source.run(tx, from_block, healthy).await.is_err() {
+                        error!("Error in event source");
+                    }
+                });
+            }));
+    }
+
+    pub fn add_strategy(&mut self, strategy: impl Strategy + 'static) {
+        self.strategies.push(Box::new(strategy));
+    }
+
+    pub fn add_executor(&mut self, executor: impl Executor + 'static) {
+        self.executors.push(Box::new(executor));
+    }
+
+    /// Runs until every collector has hung up
+    pub async fn run(mut self) {
+        let mut from_block: Option<u64> = None;
+        for strategy in &mut self.strategies {
+            let synced_block = match strategy.sync_state().await {
+                Ok(block) => block,
+                Err(e) => {
+                    error!("Failed to sync strategy state: {:?}", e);
+                    return;
+                }
+            };
+            from_block = Some(from_block.map_or(synced_block, |block| block.min(synced_block)));
+        }
+        let Some(from_block) = from_block else {
+            error!("No strategies to run");
+            return;
+        };
+
+        let (tx, rx) = crossbeam_channel::unbounded::<Event>();
+        let healthy = Arc::new(AtomicBool::new(false));
+        info!("Starting Mira event stream from block {}", from_block);
+        for collector in self.collectors.drain(..) {
+            collector(tx.clone(), from_block, healthy.clone());
+        }
+        drop(tx);
+
+        while let Ok(event) = rx.recv() {
+            for strategy in &mut self.strategies {
+                for action in strategy.process_event(event.clone()).await {
+                    // Reserves can't be trusted while the feed is reconnecting
+                    if matches!(action, Action::ExecuteCycle(_)) && !healthy.load(Ordering::SeqCst)
+                    {
+                        info!("Event feed is down, not trading");
+                        continue;
+                    }
+                    for executor in &mut self.executors {
+                        if let Some(outcome) = executor.execute(&action).await {
+                            strategy.on_execution(&action, &outcome).await;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
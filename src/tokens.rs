@@ -1,8 +1,40 @@
-use crate::types::Pool;
+use crate::types::{Fees, Pool};
 use ethers::types::U256;
 use fuels::types::AssetId;
 use std::str::FromStr;
 
+/// Assets arbitrage cycles start and end at: USDC, ETH and FUEL
+pub fn get_base_assets() -> Vec<AssetId> {
+    vec![
+        AssetId::from_str("0x286c479da40dc953bddc3bb4c453b608bba2e0ac483b077bd475174115395e6b")
+            .unwrap(),
+        AssetId::from_str("0xf8f8b6283d7fa5b672b530cbb84fcccb4ff8dc40f8176ef4544ddb1f1952ad07")
+            .unwrap(),
+        AssetId::from_str("0x1d5d97005e41cae2187a895fd8eab0506111e0e2f3331cd3912c15c24e3c1d82")
+            .unwrap(),
+    ]
+}
+
+/// ETH, the asset gas is paid in on Fuel
+pub fn get_gas_asset() -> AssetId {
+    AssetId::from_str("0xf8f8b6283d7fa5b672b530cbb84fcccb4ff8dc40f8176ef4544ddb1f1952ad07").unwrap()
+}
+
+/// USDC, the asset profits of cycles starting anywhere are compared in
+pub fn get_numeraire() -> AssetId {
+    AssetId::from_str("0x286c479da40dc953bddc3bb4c453b608bba2e0ac483b077bd475174115395e6b").unwrap()
+}
+
+// Mira's launch fee schedule, overwritten with the on-chain `fees()` in `sync_state`
+pub fn default_fees() -> Fees {
+    Fees {
+        lp_fee_volatile: 30,
+        lp_fee_stable: 5,
+        protocol_fee_volatile: 0,
+        protocol_fee_stable: 0,
+    }
+}
+
 pub fn get_pools() -> Vec<Pool> {
     let usdc_asset_id =
         AssetId::from_str("0x286c479da40dc953bddc3bb4c453b608bba2e0ac483b077bd475174115395e6b")
@@ -33,92 +65,125 @@ pub fn get_pools() -> Vec<Pool> {
             .unwrap();
     vec![
         Pool {
-            pool_name: "WETH/ETH",
+            pool_name: "WETH/ETH".to_string(),
             from: weth_asset_id,
             to: eth_asset_id,
+            is_stable: true,
             reserve_0: U256::from(0),
             reserve_1: U256::from(0),
-            fee_rate: U256::from(5),
+            decimals_0: 9,
+            decimals_1: 9,
+            fees: default_fees(),
         },
         Pool {
-            pool_name: "USDC/USDT",
+            pool_name: "USDC/USDT".to_string(),
             from: usdc_asset_id,
             to: usdt_asset_id,
+            is_stable: true,
             reserve_0: U256::from(0),
             reserve_1: U256::from(0),
-            fee_rate: U256::from(5),
+            decimals_0: 6,
+            decimals_1: 6,
+            fees: default_fees(),
         },
         Pool {
-            pool_name: "ezETH/ETH",
+            pool_name: "ezETH/ETH".to_string(),
             from: ezeth_asset_id,
             to: eth_asset_id,
+            is_stable: true,
             reserve_0: U256::from(0),
             reserve_1: U256::from(0),
-            fee_rate: U256::from(5),
+            decimals_0: 9,
+            decimals_1: 9,
+            fees: default_fees(),
         },
         Pool {
-            pool_name: "pzETH/ETH",
+            pool_name: "pzETH/ETH".to_string(),
             from: pzeth_asset_id,
             to: eth_asset_id,
+            is_stable: true,
             reserve_0: U256::from(0),
             reserve_1: U256::from(0),
-            fee_rate: U256::from(5),
+            decimals_0: 9,
+            decimals_1: 9,
+            fees: default_fees(),
         },
         Pool {
-            pool_name: "weETH/ETH",
+            pool_name: "weETH/ETH".to_string(),
             from: weeth_asset_id,
             to: eth_asset_id,
+            is_stable: true,
             reserve_0: U256::from(0),
             reserve_1: U256::from(0),
-            fee_rate: U256::from(5),
+            decimals_0: 9,
+            decimals_1: 9,
+            fees: default_fees(),
         },
         Pool {
-            pool_name: "USDC/USDF",
+            pool_name: "USDC/USDF".to_string(),
             from: usdc_asset_id,
             to: usdf_asset_id,
+            is_stable: true,
             reserve_0: U256::from(0),
             reserve_1: U256::from(0),
-            fee_rate: U256::from(5),
+            decimals_0: 6,
+            decimals_1: 9,
+            fees: default_fees(),
         },
         Pool {
-            pool_name: "USDC/ETH",
+            pool_name: "USDC/ETH".to_string(),
             from: usdc_asset_id,
             to: eth_asset_id,
+            is_stable: false,
             reserve_0: U256::from(0),
             reserve_1: U256::from(0),
-            fee_rate: U256::from(30),
+            decimals_0: 6,
+            decimals_1: 9,
+            fees: default_fees(),
         },
         Pool {
-            pool_name: "USDT/ETH",
+            pool_name: "USDT/ETH".to_string(),
             from: usdt_asset_id,
             to: eth_asset_id,
+            is_stable: false,
             reserve_0: U256::from(0),
             reserve_1: U256::from(0),
-            fee_rate: U256::from(30),
+            decimals_0: 6,
+            decimals_1: 9,
+            fees: default_fees(),
         },
         Pool {
-            pool_name: "USDC/ezETH",
+            pool_name: "USDC/ezETH".to_string(),
             from: usdc_asset_id,
             to: ezeth_asset_id,
+            is_stable: false,
             reserve_0: U256::from(0),
             reserve_1: U256::from(0),
-            fee_rate: U256::from(30),
+            decimals_0: 6,
+            decimals_1: 9,
+            fees: default_fees(),
         },
         Pool {
-            pool_name: "FUEL/ETH",
+            pool_name: "FUEL/ETH".to_string(),
             from: fuel_asset_id,
             to: eth_asset_id,
+            is_stable: false,
             reserve_0: U256::from(0),
             reserve_1: U256::from(0),
-            fee_rate: U256::from(30),
+            decimals_0: 9,
+            decimals_1: 9,
+            fees: default_fees(),
         },
         Pool {
-            pool_name: "FUEL/USDC",
+            pool_name: "FUEL/USDC".to_string(),
             from: fuel_asset_id,
             to: usdc_asset_id,
+            is_stable: false,
             reserve_0: U256::from(0),
             reserve_1: U256::from(0),
-            fee_rate: U256::from(30),
+            decimals_0: 9,
+            decimals_1: 6,
+            fees: default_fees(),
         },
     ]
 }
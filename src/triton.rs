@@ -1,16 +1,30 @@
 use crate::{
-    tokens::get_pools,
-    types::{BurnEventWithTx, Event, MintEventWithTx, Pool, SwapEventWithTx},
+    graph::TokenGraph,
+    types::{
+        parse_pool_id, BurnEventWithTx, Config, CreatePoolEventWithTx, Event, EventPosition,
+        MintEventWithTx, Pool, SwapEventWithTx,
+    },
 };
 use ethers::types::U256;
 use fuels::types::AssetId;
 use mira_v1::interface::PoolId;
 use std::{
     cell::RefCell,
-    collections::{HashMap, HashSet},
-    str::FromStr,
+    collections::{HashMap, HashSet, VecDeque},
+    hash::Hash,
 };
 
+// How many `(tx_id, log_index)` keys to remember for deduplication
+const APPLIED_EVENTS_CAPACITY: usize = 10_000;
+// How many of our own transactions to remember once applied from their receipts
+const OWN_TXS_CAPACITY: usize = 1_000;
+
+/// Sources disagree on case and the 0x prefix of transaction ids, so ids are stored and
+/// looked up lowercase without the prefix
+pub fn normalize_tx_id(tx_id: &str) -> String {
+    tx_id.trim_start_matches("0x").to_ascii_lowercase()
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct IndexedPair {
     pub index: usize,
@@ -18,8 +32,117 @@ pub struct IndexedPair {
 }
 #[derive(Debug, Clone)]
 pub struct Cycle {
+    // Asset the cycle starts and ends at
+    pub start: AssetId,
     pub cycle: Vec<IndexedPair>,
 }
+
+impl Cycle {
+    /// The same loop traversed in the opposite direction from the same start asset
+    pub fn reversed(&self) -> Cycle {
+        Cycle {
+            start: self.start,
+            cycle: self.cycle.iter().rev().copied().collect(),
+        }
+    }
+
+    /// Sorted pool indices, the same for every rotation and direction of the loop
+    pub fn loop_key(&self) -> Vec<usize> {
+        let mut loop_key = self
+            .cycle
+            .iter()
+            .map(|pair| pair.index)
+            .collect::<Vec<usize>>();
+        loop_key.sort();
+        loop_key
+    }
+}
+
+/// Set holding at most `capacity` values, evicting the oldest first
+#[derive(Debug)]
+pub struct BoundedSet<T> {
+    values: HashSet<T>,
+    order: VecDeque<T>,
+    capacity: usize,
+}
+
+impl<T: Hash + Eq + Clone> BoundedSet<T> {
+    pub fn new(capacity: usize) -> BoundedSet<T> {
+        BoundedSet {
+            values: HashSet::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.values.contains(value)
+    }
+
+    /// Inserts `value`, returning whatever was evicted to make room
+    pub fn insert(&mut self, value: T) -> Option<T> {
+        if !self.values.insert(value.clone()) {
+            return None;
+        }
+        self.order.push_back(value);
+        if self.order.len() <= self.capacity {
+            return None;
+        }
+        let oldest = self.order.pop_front()?;
+        self.values.remove(&oldest);
+        Some(oldest)
+    }
+}
+
+/// Bounded record of applied `(tx_id, log_index)` keys, with tx ids as `normalize_tx_id`
+/// returns them
+#[derive(Debug)]
+pub struct AppliedEvents {
+    keys: BoundedSet<(String, u64)>,
+    // Number of remembered keys per tx id
+    logs_by_tx: HashMap<String, usize>,
+}
+
+impl Default for AppliedEvents {
+    fn default() -> Self {
+        AppliedEvents::new(APPLIED_EVENTS_CAPACITY)
+    }
+}
+
+impl AppliedEvents {
+    pub fn new(capacity: usize) -> AppliedEvents {
+        AppliedEvents {
+            keys: BoundedSet::new(capacity),
+            logs_by_tx: HashMap::new(),
+        }
+    }
+
+    pub fn contains(&self, key: &(String, u64)) -> bool {
+        self.keys.contains(key)
+    }
+
+    /// Whether any log of `tx_id` has been applied
+    pub fn contains_tx(&self, tx_id: &str) -> bool {
+        self.logs_by_tx.contains_key(tx_id)
+    }
+
+    pub fn insert(&mut self, key: (String, u64)) {
+        if self.keys.contains(&key) {
+            return;
+        }
+        *self.logs_by_tx.entry(key.0.clone()).or_default() += 1;
+        let Some((evicted_tx, _)) = self.keys.insert(key) else {
+            return;
+        };
+        if let Some(logs) = self.logs_by_tx.get_mut(&evicted_tx) {
+            *logs -= 1;
+            if *logs == 0 {
+                self.logs_by_tx.remove(&evicted_tx);
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Triton {
     // Mapping from index to PoolId
@@ -28,127 +151,172 @@ pub struct Triton {
     pub pool_id_mapping: HashMap<PoolId, usize>,
     // Mapping of index to Pool
     pub pools: HashMap<usize, RefCell<Pool>>,
+    // Assets as nodes and pools as edges, used for cycle search
+    pub graph: TokenGraph,
     // Viable cycles found on startup
     pub cycles: Vec<Cycle>,
+    // Reverse index from pool index to the indices of the cycles that include it
+    pub cycles_by_pool: HashMap<usize, Vec<usize>>,
+    // Assets cycles start and end at
+    pub base_assets: Vec<AssetId>,
+    // Longest cycle searched for, in pools
+    pub max_hops: usize,
+    // Block height the last `sync_state` snapshot reflects
+    pub synced_block: Option<u64>,
+    // Position of the last event applied on top of the snapshot
+    pub last_position: Option<EventPosition>,
+    // Recently applied events, used to drop replays from the feed
+    pub applied_events: AppliedEvents,
+    // Events dropped because they were already applied or arrived behind `last_position`
+    pub duplicate_events: u64,
+    pub out_of_order_events: u64,
+    // Our own transactions already applied from their receipts, skipped when the feed sends them
+    pub own_txs: BoundedSet<String>,
+    // Events that took more out of a pool than its reserves hold, so ours have drifted
+    pub inconsistent_events: u64,
+    // Pools flagged by such events, cleared once `sync_state` reads them again
+    pub stale_pools: HashSet<usize>,
 }
 
 impl Default for Triton {
     fn default() -> Self {
-        Self::new()
+        Self::new(&Config::default())
     }
 }
 
 impl Triton {
-    pub fn new() -> Triton {
-        let pairs = get_pools();
-        let mut index_mapping = HashMap::new();
-        let mut pool_id_mapping = HashMap::new();
-        let mut pools = HashMap::new();
-        let mut indexed_pairs = Vec::new();
-        let mut index = 0;
-
-        for pair in pairs {
-            let is_stable = pair.fee_rate < U256::from(300);
-            let pool_id = (pair.from, pair.to, is_stable);
-            index_mapping.insert(index, pool_id);
-            pool_id_mapping.insert(pool_id, index);
-            pools.insert(index, RefCell::new(pair));
-
-            let indexed_pair = IndexedPair {
-                index: *pool_id_mapping.get(&pool_id).unwrap(),
-                pair: pool_id,
-            };
+    pub fn new(config: &Config) -> Triton {
+        Triton::new_with_pools(config.pools(), config.base_assets(), config.max_hops)
+    }
 
-            indexed_pairs.push(indexed_pair);
-            index += 1;
+    /// Builds state for `pools`, searching for cycles of up to `max_hops` pools that start
+    /// and end at any of `base_assets`
+    pub fn new_with_pools(pools: Vec<Pool>, base_assets: Vec<AssetId>, max_hops: usize) -> Triton {
+        let mut triton = Triton {
+            index_mapping: HashMap::new(),
+            pool_id_mapping: HashMap::new(),
+            pools: HashMap::new(),
+            graph: TokenGraph::default(),
+            cycles: Vec::new(),
+            cycles_by_pool: HashMap::new(),
+            base_assets,
+            max_hops,
+            synced_block: None,
+            last_position: None,
+            applied_events: AppliedEvents::default(),
+            duplicate_events: 0,
+            out_of_order_events: 0,
+            own_txs: BoundedSet::new(OWN_TXS_CAPACITY),
+            inconsistent_events: 0,
+            stale_pools: HashSet::new(),
+        };
+
+        for pair in pools {
+            triton.insert_pool(pair);
         }
 
         // Now that we have indexed pairs and pools, we can find cycles
-        let mut cycles = Vec::new();
-        let mut seen = HashSet::new();
-
-        // Going through ETH atm change to USDC later
-        let usdc_asset_id =
-            AssetId::from_str("0x286c479da40dc953bddc3bb4c453b608bba2e0ac483b077bd475174115395e6b")
-                .unwrap();
-        let fuel_asset_id =
-            AssetId::from_str("0x1d5d97005e41cae2187a895fd8eab0506111e0e2f3331cd3912c15c24e3c1d82")
-                .unwrap();
-        let eth_asset_id =
-            AssetId::from_str("0xf8f8b6283d7fa5b672b530cbb84fcccb4ff8dc40f8176ef4544ddb1f1952ad07")
-                .unwrap();
-        // We call find_cycles to find triangular arbitrage cycles (USDC to USDC, for example)
-        cycles = Triton::find_cycles(
-            &indexed_pairs,
-            fuel_asset_id, // Starting token
-            eth_asset_id, // Target token
-            5,             // Maximum hops (for triangular arbitrage)
-            &Vec::new(),
-            &mut cycles,
-            &mut seen,
-        );
+        triton.graph = TokenGraph::new(&triton.indexed_pairs());
+        let cycles = triton
+            .graph
+            .find_cycles(&triton.base_assets, triton.max_hops);
+        triton.register_cycles(cycles);
+        triton
+    }
 
-        Triton {
-            index_mapping,
-            pool_id_mapping,
-            pools,
-            cycles,
+    // Registers a pool under the next free index, returning None if it is already tracked
+    fn insert_pool(&mut self, pool: Pool) -> Option<usize> {
+        let pool_id = pool.pool_id();
+        if Triton::check_if_we_have_pool(&pool_id, &self.pool_id_mapping) {
+            return None;
         }
+
+        let index = self.pools.len();
+        self.index_mapping.insert(index, pool_id);
+        self.pool_id_mapping.insert(pool_id, index);
+        self.pools.insert(index, RefCell::new(pool));
+        Some(index)
     }
-    pub fn find_cycles(
-        pairs: &[IndexedPair],
-        token_in: AssetId,
-        token_out: AssetId,
-        max_hops: i32,
-        current_pairs: &Vec<IndexedPair>,
-        cycles: &mut Vec<Cycle>,
-        seen: &mut HashSet<usize>,
-    ) -> Vec<Cycle> {
-        let mut cycles_copy: Vec<Cycle> = cycles.clone();
-
-        for pair in pairs {
-            // Skip if already visited
-            if seen.contains(&pair.index) {
-                continue;
-            }
 
-            // Determine the next token based on the current pool
-            let temp_out = if token_in == pair.pair.0 {
-                pair.pair.1
-            } else if token_in == pair.pair.1 {
-                pair.pair.0
-            } else {
-                continue;
-            };
+    /// Adds a newly discovered pool and appends only the cycles that route through it
+    pub fn add_pool(&mut self, pool: Pool) -> Option<usize> {
+        let index = self.insert_pool(pool)?;
+        let pair = IndexedPair {
+            index,
+            pair: self.index_mapping[&index],
+        };
+        self.graph.add_pair(pair);
+        let new_cycles = self
+            .graph
+            .find_cycles_through(pair, &self.base_assets, self.max_hops);
+        self.register_cycles(new_cycles);
+        Some(index)
+    }
 
-            // Mark this pair as visited
-            let mut new_seen = seen.clone();
-            new_seen.insert(pair.index);
-
-            // If we've reached the target token, store the cycle
-            if temp_out == token_out {
-                let mut new_cycle = current_pairs.clone();
-                new_cycle.push(*pair);
-                if new_cycle.len() > 1 {
-                    // Prevent single-pool cycles
-                    cycles_copy.push(Cycle { cycle: new_cycle });
-                }
-            } else if max_hops > 1 {
-                let mut new_pairs = current_pairs.clone();
-                new_pairs.push(*pair);
-                cycles_copy = Self::find_cycles(
-                    pairs,
-                    temp_out,
-                    token_out,
-                    max_hops - 1,
-                    &new_pairs,
-                    &mut cycles_copy,
-                    &mut new_seen,
-                );
+    pub fn indexed_pairs(&self) -> Vec<IndexedPair> {
+        let mut indexed_pairs = self
+            .index_mapping
+            .iter()
+            .map(|(index, pair)| IndexedPair {
+                index: *index,
+                pair: *pair,
+            })
+            .collect::<Vec<IndexedPair>>();
+        indexed_pairs.sort_by_key(|pair| pair.index);
+        indexed_pairs
+    }
+
+    // Records cycles and indexes them under every pool they route through
+    fn register_cycles(&mut self, cycles: Vec<Cycle>) {
+        for cycle in cycles {
+            let cycle_index = self.cycles.len();
+            for pair in &cycle.cycle {
+                self.cycles_by_pool
+                    .entry(pair.index)
+                    .or_default()
+                    .push(cycle_index);
             }
+            self.cycles.push(cycle);
         }
+    }
 
-        cycles_copy
+    /// Tracks a loop found outside the startup search, returning its cycle index unless the
+    /// same loop is already tracked
+    pub fn track_cycle(&mut self, cycle: Cycle) -> Option<usize> {
+        let loop_key = cycle.loop_key();
+        let first_pool = cycle.cycle.first()?.index;
+        if self
+            .cycles_for_pool(first_pool)
+            .any(|tracked| tracked.loop_key() == loop_key)
+        {
+            return None;
+        }
+        let cycle_index = self.cycles.len();
+        self.register_cycles(vec![cycle]);
+        Some(cycle_index)
+    }
+
+    /// Cycles that route through the pool at `index`
+    pub fn cycles_for_pool(&self, index: usize) -> impl Iterator<Item = &Cycle> {
+        self.cycles_by_pool
+            .get(&index)
+            .into_iter()
+            .flatten()
+            .map(|cycle_index| &self.cycles[*cycle_index])
+    }
+
+    /// Decimals of `asset` as reported by any pool that contains it
+    pub fn asset_decimals(&self, asset: AssetId) -> Option<u8> {
+        self.pools.values().find_map(|pool| {
+            let pool = pool.borrow();
+            if pool.from == asset {
+                Some(pool.decimals_0)
+            } else if pool.to == asset {
+                Some(pool.decimals_1)
+            } else {
+                None
+            }
+        })
     }
 
     pub fn check_if_we_have_pool(
@@ -158,54 +326,188 @@ impl Triton {
         pool_id_mapping.contains_key(pool_id)
     }
 
-    fn handle_event_if_pool_exists<F>(&self, pool_id: (AssetId, AssetId, bool), event_handler: F)
+    // Runs `event_handler` if the pool is tracked. An event the reserves can't absorb is
+    // counted and its pool flagged for resync rather than applied.
+    fn handle_event_if_pool_exists<F>(
+        &mut self,
+        pool_id: (AssetId, AssetId, bool),
+        event_handler: F,
+    ) -> Option<usize>
     where
-        F: FnOnce(),
+        F: FnOnce(&Triton) -> Option<()>,
     {
-        if Triton::check_if_we_have_pool(&pool_id, &self.pool_id_mapping) {
-            event_handler();
+        let index = *self.pool_id_mapping.get(&pool_id)?;
+        if event_handler(self).is_none() {
+            self.inconsistent_events += 1;
+            self.stale_pools.insert(index);
+            log::error!(
+                "Event for pool {} doesn't fit its reserves, flagged for resync ({} so far)",
+                index,
+                self.inconsistent_events
+            );
+            return None;
         }
+        Some(index)
     }
-    pub fn process_event(&self, event: Event) {
-        match event {
-            Event::MiraSwap(event) => {
-                let pool_id = (
-                    AssetId::from_str(&event.pool_id.0.bits).expect("no asset id"),
-                    AssetId::from_str(&event.pool_id.1.bits).expect("no asset id"),
-                    event.pool_id.2,
+
+    /// Applies an event to pool state, returning the index of the pool it changed, if any
+    pub fn process_event(&mut self, event: Event) -> Option<usize> {
+        // Anything at or below the snapshot height is already reflected in the synced reserves
+        if let Some(synced_block) = self.synced_block {
+            if event.block_number() <= synced_block {
+                log::debug!(
+                    "Skipping event at block {} already in snapshot at {}",
+                    event.block_number(),
+                    synced_block
                 );
-                let handler = || self.handle_swap(&event);
-                self.handle_event_if_pool_exists(pool_id, handler);
+                return None;
             }
-            Event::MiraMint(event) => {
-                let pool_id = (
-                    AssetId::from_str(&event.pool_id.0.bits).expect("no asset id"),
-                    AssetId::from_str(&event.pool_id.1.bits).expect("no asset id"),
-                    event.pool_id.2,
+        }
+
+        let tx_id = normalize_tx_id(event.tx_id());
+        if self.own_txs.contains(&tx_id) {
+            log::debug!("Skipping event from own tx {} already applied", tx_id);
+            return None;
+        }
+
+        let position = event.position();
+        let key = (tx_id, position.log_index);
+        if self.applied_events.contains(&key) {
+            self.duplicate_events += 1;
+            log::warn!(
+                "Ignoring duplicate event {:?} at {:?} ({} so far)",
+                key,
+                position,
+                self.duplicate_events
+            );
+            return None;
+        }
+        if let Some(last_position) = self.last_position {
+            if position <= last_position {
+                self.out_of_order_events += 1;
+                log::warn!(
+                    "Ignoring event {:?} at {:?} behind last applied {:?} ({} so far)",
+                    key,
+                    position,
+                    last_position,
+                    self.out_of_order_events
                 );
-                let handler = || self.handle_mint(&event);
-                self.handle_event_if_pool_exists(pool_id, handler);
+                return None;
+            }
+        }
+
+        let Some(pool_id) = parse_pool_id(event.pool_id()) else {
+            log::error!(
+                "Dropping event {:?} at {:?} with malformed pool id {:?}",
+                key,
+                position,
+                event.pool_id()
+            );
+            return None;
+        };
+
+        let changed = match event {
+            Event::MiraSwap(event) => {
+                self.handle_event_if_pool_exists(pool_id, |triton| triton.handle_swap(&event))
+            }
+            Event::MiraMint(event) => {
+                self.handle_event_if_pool_exists(pool_id, |triton| triton.handle_mint(&event))
             }
             Event::MiraBurn(event) => {
-                let pool_id = (
-                    AssetId::from_str(&event.pool_id.0.bits).expect("no asset id"),
-                    AssetId::from_str(&event.pool_id.1.bits).expect("no asset id"),
-                    event.pool_id.2,
+                self.handle_event_if_pool_exists(pool_id, |triton| triton.handle_burn(&event))
+            }
+            Event::MiraCreatePool(event) => self.handle_create_pool(&event),
+        };
+        self.last_position = Some(position);
+        self.applied_events.insert(key);
+        changed
+    }
+
+    /// Applies swaps of one of our own transactions straight from its receipts, so reserves
+    /// reflect the trade before the feed catches up. Does nothing if the feed already applied
+    /// it. Returns the indices of the pools that changed.
+    pub fn apply_own_swaps(&mut self, tx_id: &str, swaps: &[SwapEventWithTx]) -> Vec<usize> {
+        let tx_id = normalize_tx_id(tx_id);
+        if self.applied_events.contains_tx(&tx_id) {
+            return Vec::new();
+        }
+        self.own_txs.insert(tx_id);
+
+        let mut changed = Vec::new();
+        for swap in swaps {
+            let Some(pool_id) = parse_pool_id(&swap.pool_id) else {
+                log::error!(
+                    "Own swap in tx {} has malformed pool id {:?}",
+                    swap.tx_id,
+                    swap.pool_id
                 );
-                let handler = || self.handle_burn(&event);
-                self.handle_event_if_pool_exists(pool_id, handler);
+                continue;
+            };
+            if let Some(index) =
+                self.handle_event_if_pool_exists(pool_id, |triton| triton.handle_swap(swap))
+            {
+                changed.push(index);
             }
         }
+        changed
     }
 
-    pub fn handle_swap(&self, event: &SwapEventWithTx) {
-        let pool_id = (
-            AssetId::from_str(&event.pool_id.0.bits).expect("no asset id"),
-            AssetId::from_str(&event.pool_id.1.bits).expect("no asset id"),
-            event.pool_id.2,
-        );
-        let index = self.pool_id_mapping.get(&pool_id).expect("Pool not found");
-        let pool = self.pools.get(index).expect("Pool not found");
+    /// Block height the pool state currently reflects
+    pub fn state_block(&self) -> Option<u64> {
+        self.last_position
+            .map(|position| position.block_number)
+            .max(self.synced_block)
+    }
+
+    pub fn handle_create_pool(&mut self, event: &CreatePoolEventWithTx) -> Option<usize> {
+        let Some((from, to, is_stable)) = parse_pool_id(&event.pool_id) else {
+            log::error!(
+                "Pool {:?} created in tx {} has malformed ids",
+                event.pool_id,
+                event.tx_id
+            );
+            return None;
+        };
+        // Fees are global on Mira so any pool we already track carries the current schedule
+        let fees = self
+            .pools
+            .values()
+            .next()
+            .map(|pool| pool.borrow().fees)
+            .unwrap_or_default();
+
+        let pool = Pool {
+            pool_name: format!("{:.10}/{:.10}", event.pool_id.0.bits, event.pool_id.1.bits),
+            from,
+            to,
+            is_stable,
+            reserve_0: U256::zero(),
+            reserve_1: U256::zero(),
+            decimals_0: event.decimals_0,
+            decimals_1: event.decimals_1,
+            fees,
+        };
+
+        let index = self.add_pool(pool);
+        match index {
+            Some(index) => log::info!(
+                "Discovered pool {:?} at index {} in tx {}, {} cycles tracked",
+                event.pool_id,
+                index,
+                event.tx_id,
+                self.cycles.len()
+            ),
+            None => log::debug!("Pool {:?} already tracked", event.pool_id),
+        }
+        index
+    }
+
+    /// Applies a swap to its pool's reserves. None if the pool isn't tracked or the swap takes
+    /// out more than the reserves hold.
+    pub fn handle_swap(&self, event: &SwapEventWithTx) -> Option<()> {
+        let pool = self
+            .pools
+            .get(self.pool_id_mapping.get(&parse_pool_id(&event.pool_id)?)?)?;
 
         log::debug!(
             "Before swap - Pool {:?} state: reserve_0={}, reserve_1={}",
@@ -219,14 +521,12 @@ impl Triton {
             let current_reserve_0 = pool.borrow().reserve_0;
             let new_reserve_0 = current_reserve_0
                 .checked_add(event.asset_0_in.into())
-                .and_then(|x| x.checked_sub(event.asset_0_out.into()))
-                .expect("Can't add or subtract");
+                .and_then(|x| x.checked_sub(event.asset_0_out.into()))?;
 
             let current_reserve_1 = pool.borrow().reserve_1;
             let new_reserve_1 = current_reserve_1
                 .checked_add(event.asset_1_in.into())
-                .and_then(|x| x.checked_sub(event.asset_1_out.into()))
-                .unwrap();
+                .and_then(|x| x.checked_sub(event.asset_1_out.into()))?;
 
             // Now perform the mutable borrow
             let mut pool_mut = pool.borrow_mut();
@@ -243,15 +543,14 @@ impl Triton {
             event.asset_0_out,
             event.asset_1_out
         );
+        Some(())
     }
-    pub fn handle_mint(&self, event: &MintEventWithTx) {
-        let pool_id = (
-            AssetId::from_str(&event.pool_id.0.bits).expect("no asset id"),
-            AssetId::from_str(&event.pool_id.1.bits).expect("no asset id"),
-            event.pool_id.2,
-        );
-        let index = self.pool_id_mapping.get(&pool_id).expect("Pool not found");
-        let pool = self.pools.get(index).expect("Pool not found");
+    /// Applies a mint to its pool's reserves. None if the pool isn't tracked or the reserves
+    /// would overflow.
+    pub fn handle_mint(&self, event: &MintEventWithTx) -> Option<()> {
+        let pool = self
+            .pools
+            .get(self.pool_id_mapping.get(&parse_pool_id(&event.pool_id)?)?)?;
 
         log::debug!(
             "Before mint - Pool {:?} state: reserve_0={}, reserve_1={}",
@@ -262,14 +561,10 @@ impl Triton {
 
         {
             let current_reserve_0 = pool.borrow().reserve_0;
-            let new_reserve_0 = current_reserve_0
-                .checked_add(event.asset_0_in.into())
-                .expect("Can't add");
+            let new_reserve_0 = current_reserve_0.checked_add(event.asset_0_in.into())?;
 
             let current_reserve_1 = pool.borrow().reserve_1;
-            let new_reserve_1 = current_reserve_1
-                .checked_add(event.asset_1_in.into())
-                .expect("Can't add");
+            let new_reserve_1 = current_reserve_1.checked_add(event.asset_1_in.into())?;
 
             let mut pool_mut = pool.borrow_mut();
             pool_mut.reserve_0 = new_reserve_0;
@@ -284,15 +579,14 @@ impl Triton {
         event.asset_1_in,
         event.liquidity.amount
     );
+        Some(())
     }
-    pub fn handle_burn(&self, event: &BurnEventWithTx) {
-        let pool_id = (
-            AssetId::from_str(&event.pool_id.0.bits).expect("no asset id"),
-            AssetId::from_str(&event.pool_id.1.bits).expect("no asset id"),
-            event.pool_id.2,
-        );
-        let index = self.pool_id_mapping.get(&pool_id).expect("Pool not found");
-        let pool = self.pools.get(index).expect("Pool not found");
+    /// Applies a burn to its pool's reserves. None if the pool isn't tracked or the burn takes
+    /// out more than the reserves hold.
+    pub fn handle_burn(&self, event: &BurnEventWithTx) -> Option<()> {
+        let pool = self
+            .pools
+            .get(self.pool_id_mapping.get(&parse_pool_id(&event.pool_id)?)?)?;
 
         log::debug!(
             "Before burn - Pool {:?} state: reserve_0={}, reserve_1={}",
@@ -303,14 +597,10 @@ impl Triton {
 
         {
             let current_reserve_0 = pool.borrow().reserve_0;
-            let new_reserve_0 = current_reserve_0
-                .checked_sub(event.asset_0_out.into())
-                .expect("Can't subtract");
+            let new_reserve_0 = current_reserve_0.checked_sub(event.asset_0_out.into())?;
 
             let current_reserve_1 = pool.borrow().reserve_1;
-            let new_reserve_1 = current_reserve_1
-                .checked_sub(event.asset_1_out.into())
-                .expect("Can't subtract");
+            let new_reserve_1 = current_reserve_1.checked_sub(event.asset_1_out.into())?;
 
             let mut pool_mut = pool.borrow_mut();
             pool_mut.reserve_0 = new_reserve_0;
@@ -325,5 +615,149 @@ impl Triton {
                 event.asset_1_out,
                 event.liquidity.amount
             );
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::U256;
+    use fuels::types::{Address, AssetId, Identity};
+
+    use super::{AppliedEvents, BoundedSet, Triton};
+    use crate::{
+        tokens::default_fees,
+        types::{AssetIdInternal, Event, Pool, SwapEventWithTx},
+    };
+
+    fn asset(index: u8) -> AssetId {
+        AssetId::new([index + 1; 32])
+    }
+
+    fn triton() -> Triton {
+        let pool = Pool {
+            pool_name: "0/1".to_string(),
+            from: asset(0),
+            to: asset(1),
+            is_stable: false,
+            reserve_0: U256::from(1_000_000),
+            reserve_1: U256::from(1_000_000),
+            decimals_0: 9,
+            decimals_1: 9,
+            fees: default_fees(),
+        };
+        Triton::new_with_pools(vec![pool], vec![asset(0)], 3)
+    }
+
+    fn swap(
+        tx_id: &str,
+        block_number: u64,
+        log_index: u64,
+        amount_in: u64,
+        amount_out: u64,
+    ) -> Event {
+        Event::MiraSwap(SwapEventWithTx {
+            tx_id: tx_id.to_string(),
+            block_number,
+            tx_index: 0,
+            log_index,
+            pool_id: (asset(0).into(), asset(1).into(), false),
+            recipient: Identity::Address(Address::zeroed()),
+            asset_0_in: amount_in,
+            asset_1_in: 0,
+            asset_0_out: 0,
+            asset_1_out: amount_out,
+        })
+    }
+
+    fn reserves(triton: &Triton) -> (U256, U256) {
+        let pool = triton.pools[&0].borrow();
+        (pool.reserve_0, pool.reserve_1)
+    }
+
+    #[test]
+    fn duplicate_event_is_applied_once() {
+        let mut triton = triton();
+        assert_eq!(triton.process_event(swap("0xAB", 10, 0, 100, 90)), Some(0));
+        // The same log again, as another source spells the tx id
+        assert_eq!(triton.process_event(swap("ab", 10, 0, 100, 90)), None);
+        assert_eq!(triton.duplicate_events, 1);
+        assert_eq!(
+            reserves(&triton),
+            (U256::from(1_000_100), U256::from(999_910))
+        );
+    }
+
+    #[test]
+    fn event_behind_last_position_is_dropped() {
+        let mut triton = triton();
+        assert_eq!(triton.process_event(swap("0x02", 11, 0, 100, 90)), Some(0));
+        assert_eq!(triton.process_event(swap("0x01", 10, 0, 100, 90)), None);
+        assert_eq!(triton.out_of_order_events, 1);
+        assert_eq!(
+            reserves(&triton),
+            (U256::from(1_000_100), U256::from(999_910))
+        );
+    }
+
+    #[test]
+    fn event_beyond_reserves_flags_pool() {
+        let mut triton = triton();
+        assert_eq!(
+            triton.process_event(swap("0x01", 10, 0, 0, 2_000_000)),
+            None
+        );
+        assert_eq!(triton.inconsistent_events, 1);
+        assert!(triton.stale_pools.contains(&0));
+        assert_eq!(
+            reserves(&triton),
+            (U256::from(1_000_000), U256::from(1_000_000))
+        );
+    }
+
+    #[test]
+    fn event_with_malformed_pool_id_is_dropped() {
+        let mut triton = triton();
+        let Event::MiraSwap(mut event) = swap("0x01", 10, 0, 100, 90) else {
+            unreachable!()
+        };
+        event.pool_id.0 = AssetIdInternal {
+            bits: "0xnot-an-asset".to_string(),
+        };
+        assert_eq!(triton.process_event(Event::MiraSwap(event)), None);
+        assert!(triton.stale_pools.is_empty());
+        assert_eq!(
+            reserves(&triton),
+            (U256::from(1_000_000), U256::from(1_000_000))
+        );
+        // Dropped, not applied, so the well formed log still goes through
+        assert_eq!(triton.process_event(swap("0x01", 10, 0, 100, 90)), Some(0));
+    }
+
+    #[test]
+    fn bounded_set_evicts_oldest() {
+        let mut set = BoundedSet::new(2);
+        assert_eq!(set.insert(1), None);
+        assert_eq!(set.insert(2), None);
+        // Already present, nothing moves
+        assert_eq!(set.insert(1), None);
+        assert_eq!(set.insert(3), Some(1));
+        assert!(!set.contains(&1));
+        assert!(set.contains(&2));
+        assert!(set.contains(&3));
+    }
+
+    #[test]
+    fn contains_tx_until_last_log_is_evicted() {
+        let mut applied = AppliedEvents::new(2);
+        applied.insert(("aa".to_string(), 0));
+        applied.insert(("aa".to_string(), 1));
+        applied.insert(("bb".to_string(), 0));
+        assert!(!applied.contains(&("aa".to_string(), 0)));
+        assert!(applied.contains_tx("aa"));
+
+        applied.insert(("bb".to_string(), 1));
+        assert!(!applied.contains_tx("aa"));
+        assert!(applied.contains_tx("bb"));
     }
 }
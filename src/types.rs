@@ -4,67 +4,265 @@ use ethers::types::U256;
 use fuels::types::{Address, AssetId, ContractId, Identity};
 use serde::de::{self, Deserializer};
 use serde::Deserialize;
-#[derive(Debug)]
+
+use crate::calc::NetPositiveCycle;
+
+#[derive(Debug, Clone)]
 pub enum Event {
     MiraSwap(SwapEventWithTx),
     MiraMint(MintEventWithTx),
     MiraBurn(BurnEventWithTx),
+    MiraCreatePool(CreatePoolEventWithTx),
 }
+
+/// Where an event sits on chain; orders events by block, then transaction, then log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EventPosition {
+    pub block_number: u64,
+    pub tx_index: u64,
+    pub log_index: u64,
+}
+
+impl Event {
+    pub fn tx_id(&self) -> &str {
+        match self {
+            Event::MiraSwap(event) => &event.tx_id,
+            Event::MiraMint(event) => &event.tx_id,
+            Event::MiraBurn(event) => &event.tx_id,
+            Event::MiraCreatePool(event) => &event.tx_id,
+        }
+    }
+
+    pub fn block_number(&self) -> u64 {
+        self.position().block_number
+    }
+
+    pub fn pool_id(&self) -> &PoolId {
+        match self {
+            Event::MiraSwap(event) => &event.pool_id,
+            Event::MiraMint(event) => &event.pool_id,
+            Event::MiraBurn(event) => &event.pool_id,
+            Event::MiraCreatePool(event) => &event.pool_id,
+        }
+    }
+
+    pub fn position(&self) -> EventPosition {
+        let (block_number, tx_index, log_index) = match self {
+            Event::MiraSwap(event) => (event.block_number, event.tx_index, event.log_index),
+            Event::MiraMint(event) => (event.block_number, event.tx_index, event.log_index),
+            Event::MiraBurn(event) => (event.block_number, event.tx_index, event.log_index),
+            Event::MiraCreatePool(event) => (event.block_number, event.tx_index, event.log_index),
+        };
+        EventPosition {
+            block_number,
+            tx_index,
+            log_index,
+        }
+    }
+}
+/// Something a strategy wants done, carried out by the engine's executors
 #[derive(Debug, Clone)]
-pub enum Action {}
-#[derive(Debug, Clone)]
-pub struct Config {}
+pub enum Action {
+    // Simulate and, unless dry running, send the cycle
+    ExecuteCycle(NetPositiveCycle),
+    Log(String),
+}
+/// Runtime settings, read from TOML with `TRITON_*` env overrides by `config::load_config`.
+/// Asset and contract ids are hex strings, checked when the config is loaded.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // Fuel node GraphQL endpoint
+    pub node_url: String,
+    // Chain id the node has to report before the wallet is used
+    pub chain_id: u64,
+    // Mira AMM contract
+    pub contract_id: String,
+    // Compiled swap script binary, the copy of `src/contracts` built into the bot when unset
+    pub script_path: Option<String>,
+    // Expected sha256 of the script binary, checked on startup
+    pub script_sha256: String,
+    // Pools to track, `get_pools` when empty
+    pub pools: Vec<PoolConfig>,
+    // Assets cycles start and end at, `get_base_assets` when empty
+    pub base_assets: Vec<String>,
+    // Longest cycle searched for on startup, in pools
+    pub max_hops: usize,
+    // Asset profits are compared in
+    pub numeraire: String,
+    // Asset gas is paid in
+    pub gas_asset: String,
+    // Smallest profit after gas worth trading, in raw units of the numeraire
+    pub min_net_profit: f64,
+    // Largest input value per trade, in raw units of the numeraire
+    pub max_input_value: Option<f64>,
+    // Simulate cycles without sending them
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PoolConfig {
+    pub asset_0: String,
+    pub asset_1: String,
+    pub is_stable: bool,
+}
 
 #[derive(Debug)]
 pub struct Pool {
-    pub pool_name: &'static str,
+    pub pool_name: String,
     pub from: AssetId,
     pub to: AssetId,
+    pub is_stable: bool,
     pub reserve_0: U256,
     pub reserve_1: U256,
-    pub fee_rate: U256,
+    pub decimals_0: u8,
+    pub decimals_1: u8,
+    pub fees: Fees,
 }
 
-#[derive(Debug, Deserialize)]
+/// Mira's fee schedule in basis points, as returned by `MiraAmmContract::fees()`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fees {
+    pub lp_fee_volatile: u64,
+    pub lp_fee_stable: u64,
+    pub protocol_fee_volatile: u64,
+    pub protocol_fee_stable: u64,
+}
+
+impl Fees {
+    /// Total fee charged on a swap input, LP plus protocol
+    pub fn total(&self, is_stable: bool) -> u64 {
+        if is_stable {
+            self.lp_fee_stable + self.protocol_fee_stable
+        } else {
+            self.lp_fee_volatile + self.protocol_fee_volatile
+        }
+    }
+}
+
+impl From<(u64, u64, u64, u64)> for Fees {
+    fn from(
+        (lp_fee_volatile, lp_fee_stable, protocol_fee_volatile, protocol_fee_stable): (
+            u64,
+            u64,
+            u64,
+            u64,
+        ),
+    ) -> Self {
+        Fees {
+            lp_fee_volatile,
+            lp_fee_stable,
+            protocol_fee_volatile,
+            protocol_fee_stable,
+        }
+    }
+}
+
+impl Pool {
+    /// Mira pool id for this pool: (asset_0, asset_1, is_stable)
+    pub fn pool_id(&self) -> (AssetId, AssetId, bool) {
+        (self.from, self.to, self.is_stable)
+    }
+}
 
+#[derive(Debug, Deserialize)]
 #[allow(dead_code)]
 pub struct PangeaLogData {
     chain: u64,
-    pub block_number: String,     // Hexadecimal, represented as a String
-    block_hash: String,           // Hexadecimal, represented as a String
-    transaction_index: String,    // Hexadecimal, represented as a String
-    pub transaction_hash: String, // Hexadecimal, represented as a String
-    log_index: String,            // Hexadecimal, represented as a String
-    pub id: String,               // Hexadecimal, represented as a String
-    ra: String,                   // Hexadecimal, represented as a String
-    pub rb: String,               // Hexadecimal, represented as a String
-    pc: String,                   // Hexadecimal, represented as a String
-    is: String,                   // Hexadecimal, represented as a String
-    ptr: String,                  // Hexadecimal, represented as a String
-    len: String,                  // Hexadecimal, represented as a String
-    digest: String,               // Hexadecimal, represented as a String
-    pub data: String,             // Hexadecimal, represented as a String
+    pub block_number: String,      // Hexadecimal, represented as a String
+    block_hash: String,            // Hexadecimal, represented as a String
+    pub transaction_index: String, // Hexadecimal, represented as a String
+    pub transaction_hash: String,  // Hexadecimal, represented as a String
+    pub log_index: String,         // Hexadecimal, represented as a String
+    pub id: String,                // Hexadecimal, represented as a String
+    ra: String,                    // Hexadecimal, represented as a String
+    pub rb: String,                // Hexadecimal, represented as a String
+    pc: String,                    // Hexadecimal, represented as a String
+    is: String,                    // Hexadecimal, represented as a String
+    ptr: String,                   // Hexadecimal, represented as a String
+    len: String,                   // Hexadecimal, represented as a String
+    digest: String,                // Hexadecimal, represented as a String
+    pub data: String,              // Hexadecimal, represented as a String
     pub event_name: String,
     pub decoded: String,
 }
 
 pub type PoolId = (AssetIdInternal, AssetIdInternal, bool);
+#[derive(Debug, Clone, PartialEq)]
 pub struct CreatePoolEvent {
     pub pool_id: PoolId,
     pub decimals_0: u8,
     pub decimals_1: u8,
 }
-#[derive(Debug, Deserialize)]
+
+#[derive(Debug, Clone)]
+pub struct CreatePoolEventWithTx {
+    pub tx_id: String,
+    pub block_number: u64,
+    pub tx_index: u64,
+    pub log_index: u64,
+    pub pool_id: PoolId,
+    pub decimals_0: u8,
+    pub decimals_1: u8,
+}
+#[derive(Debug, Clone)]
 pub struct AssetIdInternal {
     pub bits: String, // Matches {"bits": "..."} in JSON
 }
 
-#[derive(Debug)]
+impl AssetIdInternal {
+    pub fn asset_id(&self) -> Option<AssetId> {
+        AssetId::from_str(&self.bits).ok()
+    }
+}
+
+// Checked on the way in, so a malformed id never reaches pool lookups
+impl<'de> Deserialize<'de> for AssetIdInternal {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct RawAssetId {
+            bits: String,
+        }
+
+        let raw = RawAssetId::deserialize(deserializer)?;
+        if AssetId::from_str(&raw.bits).is_err() {
+            return Err(de::Error::custom(format!("Invalid asset id {}", raw.bits)));
+        }
+        Ok(AssetIdInternal { bits: raw.bits })
+    }
+}
+
+/// The assets and curve of a pool id, None if either asset id is malformed
+pub fn parse_pool_id(pool_id: &PoolId) -> Option<(AssetId, AssetId, bool)> {
+    Some((pool_id.0.asset_id()?, pool_id.1.asset_id()?, pool_id.2))
+}
+
+// Sources disagree on the 0x prefix and hex case, so compare the digits only
+impl PartialEq for AssetIdInternal {
+    fn eq(&self, other: &Self) -> bool {
+        self.bits
+            .trim_start_matches("0x")
+            .eq_ignore_ascii_case(other.bits.trim_start_matches("0x"))
+    }
+}
+
+impl From<AssetId> for AssetIdInternal {
+    fn from(asset_id: AssetId) -> Self {
+        AssetIdInternal {
+            bits: format!("{:#x}", asset_id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Asset {
     pub id: AssetIdInternal,
     pub amount: u64,
 }
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct MintEvent {
     pub pool_id: PoolId,
     pub recipient: Identity,
@@ -73,7 +271,7 @@ pub struct MintEvent {
     pub asset_1_in: u64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct BurnEvent {
     pub pool_id: PoolId,
     pub recipient: Identity,
@@ -82,7 +280,7 @@ pub struct BurnEvent {
     pub asset_1_out: u64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SwapEvent {
     pub pool_id: PoolId,
     pub recipient: Identity,
@@ -92,9 +290,12 @@ pub struct SwapEvent {
     pub asset_1_out: u64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SwapEventWithTx {
     pub tx_id: String,
+    pub block_number: u64,
+    pub tx_index: u64,
+    pub log_index: u64,
     pub pool_id: PoolId,
     pub recipient: Identity,
     pub asset_0_in: u64,
@@ -103,9 +304,12 @@ pub struct SwapEventWithTx {
     pub asset_1_out: u64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MintEventWithTx {
     pub tx_id: String,
+    pub block_number: u64,
+    pub tx_index: u64,
+    pub log_index: u64,
     pub pool_id: PoolId,
     pub recipient: Identity,
     pub liquidity: Asset,
@@ -113,9 +317,12 @@ pub struct MintEventWithTx {
     pub asset_1_in: u64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct BurnEventWithTx {
     pub tx_id: String,
+    pub block_number: u64,
+    pub tx_index: u64,
+    pub log_index: u64,
     pub pool_id: PoolId,
     pub recipient: Identity,
     pub liquidity: Asset,
@@ -123,6 +330,29 @@ pub struct BurnEventWithTx {
     pub asset_1_out: u64,
 }
 
+// Pangea encodes an Identity as {"Address": {"bits": ...}} or {"ContractId": {"bits": ...}}
+fn parse_recipient<E: de::Error>(recipient: &serde_json::Value) -> Result<Identity, E> {
+    if let Some(address) = recipient.get("Address") {
+        if let Some(bits) = address.get("bits").and_then(|b| b.as_str()) {
+            Ok(Identity::Address(
+                Address::from_str(bits).map_err(E::custom)?,
+            ))
+        } else {
+            Err(E::custom("Invalid Address format"))
+        }
+    } else if let Some(contract) = recipient.get("ContractId") {
+        if let Some(bits) = contract.get("bits").and_then(|b| b.as_str()) {
+            Ok(Identity::ContractId(
+                ContractId::from_str(bits).map_err(E::custom)?,
+            ))
+        } else {
+            Err(E::custom("Invalid ContractId format"))
+        }
+    } else {
+        Err(E::custom("Invalid recipient structure"))
+    }
+}
+
 impl<'de> Deserialize<'de> for SwapEvent {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -134,55 +364,16 @@ impl<'de> Deserialize<'de> for SwapEvent {
             asset_0_out: u64,
             asset_1_in: u64,
             asset_1_out: u64,
-            pool_id: Vec<serde_json::Value>,
+            pool_id: PoolId,
             recipient: serde_json::Value,
         }
 
         let raw = RawSwapEvent::deserialize(deserializer)?;
 
-        // Parse pool_id
-        if raw.pool_id.len() != 3 {
-            return Err(de::Error::custom("pool_id must contain exactly 3 items"));
-        }
-
-        let asset_0 = match &raw.pool_id[0] {
-            serde_json::Value::Object(obj) if obj.contains_key("bits") => AssetIdInternal {
-                bits: obj["bits"].as_str().unwrap().to_string(),
-            },
-            _ => return Err(de::Error::custom("Invalid format for pool_id[0]")),
-        };
-
-        let asset_1 = match &raw.pool_id[1] {
-            serde_json::Value::Object(obj) if obj.contains_key("bits") => AssetIdInternal {
-                bits: obj["bits"].as_str().unwrap().to_string(),
-            },
-            _ => return Err(de::Error::custom("Invalid format for pool_id[1]")),
-        };
-
-        let flag = match raw.pool_id[2] {
-            serde_json::Value::Bool(flag) => flag,
-            _ => return Err(de::Error::custom("Invalid format for pool_id[2]")),
-        };
-
-        // Parse recipient
-        let recipient = if let Some(address) = raw.recipient.get("Address") {
-            if let Some(bits) = address.get("bits").and_then(|b| b.as_str()) {
-                Identity::Address(Address::from_str(bits).map_err(de::Error::custom)?)
-            } else {
-                return Err(de::Error::custom("Invalid Address format"));
-            }
-        } else if let Some(contract) = raw.recipient.get("ContractId") {
-            if let Some(bits) = contract.get("bits").and_then(|b| b.as_str()) {
-                Identity::ContractId(ContractId::from_str(bits).map_err(de::Error::custom)?)
-            } else {
-                return Err(de::Error::custom("Invalid ContractId format"));
-            }
-        } else {
-            return Err(de::Error::custom("Invalid recipient structure"));
-        };
+        let recipient = parse_recipient(&raw.recipient)?;
 
         Ok(SwapEvent {
-            pool_id: (asset_0, asset_1, flag),
+            pool_id: raw.pool_id,
             recipient,
             asset_0_in: raw.asset_0_in,
             asset_1_in: raw.asset_1_in,
@@ -202,58 +393,19 @@ impl<'de> Deserialize<'de> for MintEvent {
             asset_0_in: u64,
             asset_1_in: u64,
             liquidity: Asset, // Now directly deserialize liquidity as Asset
-            pool_id: Vec<serde_json::Value>,
+            pool_id: PoolId,
             recipient: serde_json::Value,
         }
 
         let raw = RawMintEvent::deserialize(deserializer)?;
 
-        // Parse pool_id
-        if raw.pool_id.len() != 3 {
-            return Err(de::Error::custom("pool_id must contain exactly 3 items"));
-        }
-
-        let asset_0 = match &raw.pool_id[0] {
-            serde_json::Value::Object(obj) if obj.contains_key("bits") => AssetIdInternal {
-                bits: obj["bits"].as_str().unwrap().to_string(),
-            },
-            _ => return Err(de::Error::custom("Invalid format for pool_id[0]")),
-        };
-
-        let asset_1 = match &raw.pool_id[1] {
-            serde_json::Value::Object(obj) if obj.contains_key("bits") => AssetIdInternal {
-                bits: obj["bits"].as_str().unwrap().to_string(),
-            },
-            _ => return Err(de::Error::custom("Invalid format for pool_id[1]")),
-        };
-
-        let flag = match raw.pool_id[2] {
-            serde_json::Value::Bool(flag) => flag,
-            _ => return Err(de::Error::custom("Invalid format for pool_id[2]")),
-        };
-
-        // Parse recipient
-        let recipient = if let Some(address) = raw.recipient.get("Address") {
-            if let Some(bits) = address.get("bits").and_then(|b| b.as_str()) {
-                Identity::Address(Address::from_str(bits).map_err(de::Error::custom)?)
-            } else {
-                return Err(de::Error::custom("Invalid Address format"));
-            }
-        } else if let Some(contract) = raw.recipient.get("ContractId") {
-            if let Some(bits) = contract.get("bits").and_then(|b| b.as_str()) {
-                Identity::ContractId(ContractId::from_str(bits).map_err(de::Error::custom)?)
-            } else {
-                return Err(de::Error::custom("Invalid ContractId format"));
-            }
-        } else {
-            return Err(de::Error::custom("Invalid recipient structure"));
-        };
+        let recipient = parse_recipient(&raw.recipient)?;
 
         // `liquidity` is now directly an Asset, so it's already handled in the RawMintEvent
         let liquidity = raw.liquidity;
 
         Ok(MintEvent {
-            pool_id: (asset_0, asset_1, flag),
+            pool_id: raw.pool_id,
             recipient,
             liquidity,
             asset_0_in: raw.asset_0_in,
@@ -273,58 +425,19 @@ impl<'de> Deserialize<'de> for BurnEvent {
             asset_0_out: u64,
             asset_1_out: u64,
             liquidity: Asset, // Now directly deserialize liquidity as Asset
-            pool_id: Vec<serde_json::Value>,
+            pool_id: PoolId,
             recipient: serde_json::Value,
         }
 
         let raw = RawBurnEvent::deserialize(deserializer)?;
 
-        // Parse pool_id
-        if raw.pool_id.len() != 3 {
-            return Err(de::Error::custom("pool_id must contain exactly 3 items"));
-        }
-
-        let asset_0 = match &raw.pool_id[0] {
-            serde_json::Value::Object(obj) if obj.contains_key("bits") => AssetIdInternal {
-                bits: obj["bits"].as_str().unwrap().to_string(),
-            },
-            _ => return Err(de::Error::custom("Invalid format for pool_id[0]")),
-        };
-
-        let asset_1 = match &raw.pool_id[1] {
-            serde_json::Value::Object(obj) if obj.contains_key("bits") => AssetIdInternal {
-                bits: obj["bits"].as_str().unwrap().to_string(),
-            },
-            _ => return Err(de::Error::custom("Invalid format for pool_id[1]")),
-        };
-
-        let flag = match raw.pool_id[2] {
-            serde_json::Value::Bool(flag) => flag,
-            _ => return Err(de::Error::custom("Invalid format for pool_id[2]")),
-        };
-
-        // Parse recipient
-        let recipient = if let Some(address) = raw.recipient.get("Address") {
-            if let Some(bits) = address.get("bits").and_then(|b| b.as_str()) {
-                Identity::Address(Address::from_str(bits).map_err(de::Error::custom)?)
-            } else {
-                return Err(de::Error::custom("Invalid Address format"));
-            }
-        } else if let Some(contract) = raw.recipient.get("ContractId") {
-            if let Some(bits) = contract.get("bits").and_then(|b| b.as_str()) {
-                Identity::ContractId(ContractId::from_str(bits).map_err(de::Error::custom)?)
-            } else {
-                return Err(de::Error::custom("Invalid ContractId format"));
-            }
-        } else {
-            return Err(de::Error::custom("Invalid recipient structure"));
-        };
+        let recipient = parse_recipient(&raw.recipient)?;
 
         // `liquidity` is now directly an Asset, so it's already handled in the RawBurnEvent
         let liquidity = raw.liquidity;
 
         Ok(BurnEvent {
-            pool_id: (asset_0, asset_1, flag),
+            pool_id: raw.pool_id,
             recipient,
             liquidity,
             asset_0_out: raw.asset_0_out,
@@ -333,6 +446,29 @@ impl<'de> Deserialize<'de> for BurnEvent {
     }
 }
 
+// Custom Deserialize implementation for CreatePoolEvent
+impl<'de> Deserialize<'de> for CreatePoolEvent {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct RawCreatePoolEvent {
+            pool_id: PoolId,
+            decimals_0: u8,
+            decimals_1: u8,
+        }
+
+        let raw = RawCreatePoolEvent::deserialize(deserializer)?;
+
+        Ok(CreatePoolEvent {
+            pool_id: raw.pool_id,
+            decimals_0: raw.decimals_0,
+            decimals_1: raw.decimals_1,
+        })
+    }
+}
+
 // Custom Deserialize implementation for Asset
 impl<'de> Deserialize<'de> for Asset {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -353,3 +489,33 @@ impl<'de> Deserialize<'de> for Asset {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::SwapEvent;
+
+    fn swap_json(asset_0: &str) -> String {
+        format!(
+            r#"{{"asset_0_in":100,"asset_0_out":0,"asset_1_in":0,"asset_1_out":90,"pool_id":[{{"bits":"{}"}},{{"bits":"0x{}"}},false],"recipient":{{"Address":{{"bits":"0x{}"}}}}}}"#,
+            asset_0,
+            "22".repeat(32),
+            "33".repeat(32)
+        )
+    }
+
+    #[test]
+    fn swap_event_deserializes() {
+        let event: SwapEvent =
+            serde_json::from_str(&swap_json(&format!("0x{}", "11".repeat(32)))).unwrap();
+        assert_eq!(event.asset_0_in, 100);
+        assert_eq!(event.asset_1_out, 90);
+        assert!(!event.pool_id.2);
+    }
+
+    #[test]
+    fn malformed_asset_id_is_rejected() {
+        assert!(serde_json::from_str::<SwapEvent>(&swap_json("0xnot-an-asset")).is_err());
+        // Right characters, wrong length
+        assert!(serde_json::from_str::<SwapEvent>(&swap_json("0x1111")).is_err());
+    }
+}
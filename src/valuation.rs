@@ -0,0 +1,247 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use ethers::types::I256;
+use fuels::{accounts::provider::Provider, prelude::Result, types::AssetId};
+
+use crate::{
+    calc::{mid_rate, u256_to_f64, NetPositiveCycle},
+    triton::Triton,
+    types::Config,
+};
+
+// Blocks ahead the gas price estimate has to hold for
+const GAS_PRICE_BLOCK_HORIZON: u32 = 10;
+// Number of cycles handed to the executor per update
+const TOP_CYCLES: usize = 5;
+// How long a simulated gas figure is trusted before the cycle shape is simulated again
+const GAS_ESTIMATE_TTL: Duration = Duration::from_secs(600);
+
+/// A cycle valued in the numeraire, in its raw units
+#[derive(Debug, Clone)]
+pub struct ValuedCycle {
+    pub cycle: NetPositiveCycle,
+    pub profit: f64,
+    pub gas_cost: f64,
+    pub net_profit: f64,
+}
+
+/// Converts cycle profits into one numeraire at current mid prices and charges them for the
+/// swap script's gas, so cycles starting at different assets can be compared
+#[derive(Debug)]
+pub struct Valuation {
+    pub numeraire: AssetId,
+    // Asset gas is paid in
+    pub gas_asset: AssetId,
+    // Raw units of `gas_asset` charged per unit of gas
+    pub gas_price: f64,
+    // Gas the swap script used in simulation and when, by whether each hop is stable
+    gas_by_shape: HashMap<Vec<bool>, (u64, Instant)>,
+    // Smallest net profit worth sending, in the numeraire
+    pub min_net_profit: f64,
+    // Largest input a cycle may spend, in the numeraire
+    pub max_input_value: Option<f64>,
+}
+
+impl Valuation {
+    pub fn new(config: &Config) -> Valuation {
+        Valuation {
+            numeraire: config.numeraire(),
+            gas_asset: config.gas_asset(),
+            gas_price: 0.0,
+            gas_by_shape: HashMap::new(),
+            min_net_profit: config.min_net_profit,
+            max_input_value: config.max_input_value,
+        }
+    }
+
+    /// Reads the node's gas price estimate, scaled by the chain's gas price factor
+    pub async fn refresh_gas_price(&mut self, provider: &Provider) -> Result<()> {
+        let gas_price = provider
+            .estimate_gas_price(GAS_PRICE_BLOCK_HORIZON)
+            .await?
+            .gas_price;
+        let gas_price_factor = provider
+            .consensus_parameters()
+            .fee_params()
+            .gas_price_factor();
+        self.gas_price = gas_price as f64 / gas_price_factor as f64;
+        Ok(())
+    }
+
+    /// Whether a cycle of this shape has been simulated within `GAS_ESTIMATE_TTL`
+    pub fn has_gas_estimate(&self, cycle: &NetPositiveCycle) -> bool {
+        self.gas_used(cycle).is_some()
+    }
+
+    /// Records the gas a simulated run of the swap script over `cycle` used
+    pub fn record_gas(&mut self, cycle: &NetPositiveCycle, gas_used: u64) {
+        self.gas_by_shape
+            .insert(gas_shape(cycle), (gas_used, Instant::now()));
+    }
+
+    // Stable hops run the invariant solver and cost far more than volatile ones, so
+    // estimates are shared between cycles with the same hops in the same order
+    fn gas_used(&self, cycle: &NetPositiveCycle) -> Option<u64> {
+        match self.gas_by_shape.get(&gas_shape(cycle)) {
+            Some((gas_used, measured)) if measured.elapsed() < GAS_ESTIMATE_TTL => Some(*gas_used),
+            _ => None,
+        }
+    }
+
+    /// Price of one raw unit of `asset` in raw units of the numeraire
+    pub fn price(&self, triton: &Triton, asset: AssetId) -> Option<f64> {
+        price_in(triton, asset, self.numeraire)
+    }
+
+    /// Cost of running the swap script over `cycle`, in the numeraire. None until a cycle of
+    /// the same shape has been simulated, or once that simulation is stale.
+    pub fn gas_cost(&self, triton: &Triton, cycle: &NetPositiveCycle) -> Option<f64> {
+        let gas_used = self.gas_used(cycle)?;
+        Some(gas_used as f64 * self.gas_price * self.price(triton, self.gas_asset)?)
+    }
+
+    pub fn value(&self, triton: &Triton, cycle: NetPositiveCycle) -> Option<ValuedCycle> {
+        let profit = i256_to_f64(cycle.profit) * self.price(triton, cycle.token_in)?;
+        let gas_cost = self.gas_cost(triton, &cycle)?;
+        Some(ValuedCycle {
+            cycle,
+            profit,
+            gas_cost,
+            net_profit: profit - gas_cost,
+        })
+    }
+
+    /// The `TOP_CYCLES` cycles that clear `min_net_profit` once gas is paid, most profitable
+    /// first. Cycles that can't be priced, whose gas hasn't been measured yet or that spend
+    /// more than `max_input_value` are left out.
+    pub fn rank(&self, triton: &Triton, cycles: Vec<NetPositiveCycle>) -> Vec<ValuedCycle> {
+        let mut valued = cycles
+            .into_iter()
+            .filter(|cycle| self.within_input_limit(triton, cycle))
+            .filter_map(|cycle| self.value(triton, cycle))
+            .filter(|cycle| cycle.net_profit > self.min_net_profit.max(0.0))
+            .collect::<Vec<ValuedCycle>>();
+        valued.sort_by(|a, b| b.net_profit.total_cmp(&a.net_profit));
+        valued.truncate(TOP_CYCLES);
+        valued
+    }
+
+    fn within_input_limit(&self, triton: &Triton, cycle: &NetPositiveCycle) -> bool {
+        let Some(max_input_value) = self.max_input_value else {
+            return true;
+        };
+        match self.price(triton, cycle.token_in) {
+            Some(price) => u256_to_f64(cycle.optimal_in) * price <= max_input_value,
+            None => false,
+        }
+    }
+}
+
+/// Price of one raw unit of `asset` in raw units of `numeraire`, chaining pool mid prices
+/// along the route with the fewest pools
+pub fn price_in(triton: &Triton, asset: AssetId, numeraire: AssetId) -> Option<f64> {
+    if asset == numeraire {
+        return Some(1.0);
+    }
+
+    let mut prices = HashMap::from([(asset, 1.0)]);
+    let mut queue = VecDeque::from([asset]);
+    while let Some(token) = queue.pop_front() {
+        let price = prices[&token];
+        for edge in triton.graph.edges(&token) {
+            if prices.contains_key(&edge.token_out) {
+                continue;
+            }
+            let Some(pool) = triton.pools.get(&edge.pair.index) else {
+                continue;
+            };
+            let Some(rate) = mid_rate(&pool.borrow(), token) else {
+                continue;
+            };
+            if edge.token_out == numeraire {
+                return Some(price * rate);
+            }
+            prices.insert(edge.token_out, price * rate);
+            queue.push_back(edge.token_out);
+        }
+    }
+    None
+}
+
+/// Whether each hop of `cycle` is stable, the key gas estimates are shared under
+pub fn gas_shape(cycle: &NetPositiveCycle) -> Vec<bool> {
+    cycle
+        .cycle_ids
+        .iter()
+        .map(|(_, _, is_stable)| *is_stable)
+        .collect()
+}
+
+fn i256_to_f64(value: I256) -> f64 {
+    let magnitude = u256_to_f64(value.unsigned_abs());
+    if value.is_negative() {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{I256, U256};
+    use fuels::types::AssetId;
+
+    use super::Valuation;
+    use crate::{
+        calc::{NetPositiveCycle, SearchDiagnostics},
+        tokens::{default_fees, get_gas_asset, get_numeraire},
+        triton::Triton,
+        types::{Config, Pool},
+    };
+
+    fn cycle(token_in: AssetId, profit: i64) -> NetPositiveCycle {
+        NetPositiveCycle {
+            token_in,
+            profit: I256::from(profit),
+            optimal_in: U256::from(1_000),
+            swap_amounts: Vec::new(),
+            cycle_ids: vec![(token_in, token_in, false)],
+            search: SearchDiagnostics::default(),
+        }
+    }
+
+    #[test]
+    fn ranks_in_numeraire_not_raw_units() {
+        let usdc = get_numeraire();
+        let eth = get_gas_asset();
+        // 3000 USDC (6 decimals) against 1 ETH (9 decimals), a raw unit of ETH is worth 3 of USDC
+        let pool = Pool {
+            pool_name: "USDC/ETH".to_string(),
+            from: usdc,
+            to: eth,
+            is_stable: false,
+            reserve_0: U256::from(3_000_000_000u64),
+            reserve_1: U256::from(1_000_000_000u64),
+            decimals_0: 6,
+            decimals_1: 9,
+            fees: default_fees(),
+        };
+        let triton = Triton::new_with_pools(vec![pool], vec![usdc, eth], 2);
+        let mut valuation = Valuation::new(&Config::default());
+
+        // More raw profit starting at USDC, but less once the ETH cycle is priced in USDC
+        let usdc_cycle = cycle(usdc, 1_000);
+        let eth_cycle = cycle(eth, 500);
+        valuation.record_gas(&usdc_cycle, 0);
+
+        let ranked = valuation.rank(&triton, vec![usdc_cycle, eth_cycle]);
+        assert_eq!(ranked.len(), 2);
+        assert_eq!(ranked[0].cycle.token_in, eth);
+        assert_eq!(ranked[0].net_profit, 1_500.0);
+        assert_eq!(ranked[1].cycle.token_in, usdc);
+        assert_eq!(ranked[1].net_profit, 1_000.0);
+    }
+}